@@ -7,7 +7,7 @@ import {
   getDocs,
   documentId,
 
-  Timestamp, doc, arrayUnion, updateDoc,
+  Timestamp, doc, arrayUnion, updateDoc, getDoc, setDoc,
 } from "firebase/firestore";
 import { db } from "./configuration"; // Adjust path as needed
 import {
@@ -47,7 +47,8 @@ interface Item {
   createdAt: Timestamp;
   groupMembers?: string[];
   gameIds?: { created_by: string, game_id: string, time_created: number, prize: string }[];
-  gameResults?: { winner: string, prize: string }[];
+  // winner is null for draws and team games, the winning team and its members are in team and winners
+  gameResults?: { winner: string | null, team?: string | null, winners?: string[], prize: string }[];
 }
 
 export default function ItemView({ id }: ItemViewProps) {
@@ -177,10 +178,16 @@ export default function ItemView({ id }: ItemViewProps) {
         if (data.type !== "Success") alert("error: " + JSON.stringify(data));
         else {
           const game_id: Item['gameIds'][number] = { prize, created_by: user?.uid, game_id:  data.game_id, time_created: Date.now() };
+          const players = data.users as { user_id: string, access_token: string }[];
 
           if (id) {
             const itemDocRef = doc(db, "items", id);
 
+            // each player's token goes in their own document, so nobody can read anyone else's
+            for (const {user_id, access_token} of players) {
+              await setDoc(doc(db, "users", user_id, "gameTokens", game_id.game_id), {access_token});
+            }
+
             await updateDoc(itemDocRef, {
               gameIds: arrayUnion(game_id),
             });
@@ -191,9 +198,9 @@ export default function ItemView({ id }: ItemViewProps) {
               props: {
                 game_id: game_id.game_id,
                 all_users: Object.fromEntries(Object.entries(allUsers || {}).filter(([uuid]) => item.groupMembers!.includes(uuid))),
-                access_token: user!.uid!,
+                access_token: players.find(({user_id}) => user_id === user!.uid!)?.access_token ?? "",
                 user_id: user!.uid!,
-                url: GAME_SERVER_PLAY,
+                url: data.game_url ?? GAME_SERVER_PLAY,
                 prize
               }
             });
@@ -302,14 +309,15 @@ export default function ItemView({ id }: ItemViewProps) {
                   <Button onClick={dailyPlay}>Create a Lobby</Button>
 
                   {item && allUsers && item.gameIds && item.gameIds.map(({game_id, created_by, time_created, prize}) => (
-                    <a key={game_id} style={{textDecoration: 'underline'}} onClick={() => {
+                    <a key={game_id} style={{textDecoration: 'underline'}} onClick={async () => {
+                      const token = await getDoc(doc(db, "users", user!.uid!, "gameTokens", game_id));
                       setSnakeProps({
                         didCreate: false,
                         props: {
                           prize,
                           game_id,
                           user_id: user!.uid!,
-                          access_token: user!.uid!,
+                          access_token: token.data()?.access_token ?? "",
                           url: GAME_SERVER_PLAY,
                           all_users: Object.fromEntries(Object.entries(allUsers).filter(([uuid]) => item.groupMembers!.includes(uuid)))
                         }
@@ -357,10 +365,12 @@ export default function ItemView({ id }: ItemViewProps) {
                       </tr>
                       </thead>
                       <tbody>
-                      {Object.entries(item.gameResults).map(([_, {winner, prize}], index) => (
+                      {Object.entries(item.gameResults).map(([_, {winner, team, winners, prize}], index) => (
                           <tr key={index} className={index % 2 === 0 ? "bg-white" : "bg-gray-50"}>
                             <td className="border border-gray-300 px-4 py-2">
-                              {allUsers[winner] || "Unknown"}
+                              {team
+                                  ? `${team} (${(winners || []).map(user_id => allUsers[user_id] || "Unknown").join(", ")})`
+                                  : winner ? allUsers[winner] || "Unknown" : "Draw"}
                             </td>
                             <td className="border border-gray-300 px-4 py-2 text-right">
                               {prize}
@@ -435,7 +445,7 @@ export default function ItemView({ id }: ItemViewProps) {
       {
         snakeProps && (
             <div style={{position: 'fixed', top: 0, left: 0, width: '100%', height: '100%', background: 'rgba(0,0,0,0.5)', zIndex: 100, display: 'grid', placeItems: 'center'}}>
-              <SnakeGame {...snakeProps.props} onOver={({winner,results}) => setTimeout(async () => {
+              <SnakeGame {...snakeProps.props} onOver={({winner,results,result}) => setTimeout(async () => {
                 
                 setSnakeProps(undefined);
                 if (snakeProps?.didCreate) {
@@ -446,12 +456,20 @@ export default function ItemView({ id }: ItemViewProps) {
                     newContibutions[contrib.user_id] = (newContibutions[contrib.user_id] || 0) + contrib.amount_spent;
                   }
 
-                  console.log(newContibutions, {winner, prize: snakeProps.props.prize});
+                  // a team game has no single winner, the whole team won
+                  const gameResult = {
+                    winner,
+                    team: result.type === "WinningTeam" ? result.team : null,
+                    winners: result.type === "WinningTeam" ? result.user_ids : result.type === "Winner" ? [result.user_id] : [],
+                    prize: snakeProps.props.prize
+                  };
+
+                  console.log(newContibutions, gameResult);
 
                   await updateDoc(itemDocRef, {
                     contributions: newContibutions,
                     gameIds: [],
-                    gameResults: arrayUnion({winner, prize: snakeProps.props.prize}),
+                    gameResults: arrayUnion(gameResult),
 
                   });
                 }
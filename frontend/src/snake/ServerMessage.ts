@@ -44,23 +44,175 @@ export type RecentPowerUp = {
 	power_up: PowerUps
 }
 
+export type PlayerStats = {
+	user_id: string,
+	apples_eaten: number,
+	kills: number,
+	deaths: number,
+	max_length: number,
+	time_alive_ms: number
+}
+
+export type MatchResult = {
+	type: "Winner",
+	user_id: string
+} | {
+	type: "WinningTeam",
+	team: string,
+	user_ids: string[]
+} | {
+	type: "Draw",
+	user_ids: string[],
+	teams: string[]
+} | {
+	type: "Interrupted",
+	user_ids: string[]
+}
+
+export type Team = {
+	team: string,
+	user_ids: string[]
+}
+
+export type TeamAmountSpent = {
+	team: string,
+	amount_spent: number
+}
+
+export type SentBoard = {
+	size: [number, number],
+	walls: [number, number][],
+	teleports: [[number, number], [number, number]][]
+}
+
+export type GameEvent = {
+	type: "Died",
+	user_id: string,
+	killed_by: string | null
+} | {
+	type: "ExtraLifeUsed",
+	user_id: string,
+	killed_by: string | null
+} | {
+	type: "AteApple",
+	user_id: string,
+	apple: [number, number]
+} | {
+	type: "PowerUpUsed",
+	user_id: string,
+	power_up: PowerUps,
+	target: string | null
+} | {
+	type: "Revived",
+	user_id: string,
+	head: [number, number]
+} | {
+	type: "TurnCompensated",
+	user_id: string,
+	moves: number
+}
+
+export type GameError = {
+	type: "UnknownPlayer",
+	user_id: string
+} | {
+	type: "NotAlive"
+} | {
+	type: "NotDead"
+} | {
+	type: "TargetNotAlive",
+	user_id: string
+} | {
+	type: "TargetIsTeammate",
+	user_id: string
+} | {
+	type: "SuddenDeath"
+} | {
+	type: "NoRoomToRevive"
+}
+
+export type ConnectionError = {
+	type: "InvalidMessage",
+	reason: string
+} | {
+	type: "NotAuthenticated"
+} | {
+	type: "UnknownGame"
+} | {
+	type: "InvalidAccessToken"
+} | {
+	type: "GameStopped"
+} | {
+	type: "RateLimited",
+	message_type: string
+} | {
+	type: "Disconnected",
+	reason: string
+} | {
+	type: "GameOnOtherNode",
+	url: string
+}
+
+export type PlayerPresence = {
+	user_id: string,
+	connected: boolean,
+	rtt_ms: number | null
+}
+
 export type ServerMessage  ={
 	type: "Authenticated"
 } | {
 	type: "ReadyStatus",
 	status: ReadyStatus[]
 } | {
-	type: "StartGame"
+	type: "StartGame",
+	board: SentBoard,
+	teams: Team[]
+} | {
+	type: "SuddenDeath"
+} | {
+	type: "ArenaShrunk",
+	walls: [number, number][]
+} | {
+	type: "ServerShuttingDown",
+	deadline_secs: number
 } | {
 	type: "GameOver",
-	winner: string,
-	amounts_spent: AmountSpent[]
+	winner: string | null,
+	result: MatchResult,
+	amounts_spent: AmountSpent[],
+	team_amounts_spent: TeamAmountSpent[],
+	stats: PlayerStats[]
+} | {
+	type: "Events",
+	events: GameEvent[]
+} | {
+	type: "GameError",
+	error: GameError
+} | {
+	type: "ConnectionError",
+	error: ConnectionError
+} | {
+	type: "Ping",
+	id: number,
+	server_time_ms: number
+} | {
+	type: "Latency",
+	rtt_ms: number,
+	clock_offset_ms: number
+} | {
+	type: "Presence",
+	players: PlayerPresence[]
 } | {
 	type: "GameState",
+	tick: number,
+	server_time_ms: number,
+	last_processed_seq: Record<string, number>,
 	apples: [number, number][],
 	snakes: SentSnake[],
 	just_ate_apple: string[],
-	recent_power_ups: RecentPowerUp[]
+	recent_power_ups: RecentPowerUp[],
+	stats: PlayerStats[]
 }
 
 
@@ -73,8 +225,14 @@ export type ClientMessage = {
 	power_up: PowerUps
 } | {
 	type: "SetDirection",
-	direction: Direction
+	direction: Direction,
+	seq?: number,
+	tick?: number
 } | {
 	type: "SetReady",
 	ready: boolean
+} | {
+	type: "Pong",
+	id: number,
+	client_time_ms: number
 };
\ No newline at end of file
@@ -2,9 +2,11 @@ import React, {useEffect, useMemo, useRef, useState} from "react";
 import {
 	AmountSpent,
 	ClientMessage,
+	MatchResult,
 	PowerUps,
 	ReadyStatus,
 	RecentPowerUp,
+	SentBoard,
 	ServerMessage
 } from "@/snake/ServerMessage.ts";
 
@@ -15,10 +17,44 @@ export interface SnakeGameProps {
 	access_token: string,
 	game_id: string,
 	url: string,
-	onOver: (results: {results: AmountSpent[], winner: string}) => void,
+	onOver: (results: {results: AmountSpent[], winner: string | null, result: MatchResult}) => void,
 	prize: string
 }
 
+// pixels per block
+const CELL_SIZE = 10;
+// used until the game starts and the server sends the real board
+const DEFAULT_BOARD_SIZE: [number, number] = [100, 50];
+
+const TELEPORT_COLORS = [
+	"cyan",
+	"gold",
+	"lime",
+	"deeppink"];
+
+type Board = {
+	size: [number, number],
+	// "x,y" of every wall block
+	walls: Set<string>,
+	// "x,y" of each teleport end to the other end
+	teleports: Map<string, [number, number]>,
+	pairs: [[number, number], [number, number]][]
+}
+
+function toBoard(sent: SentBoard): Board {
+	const teleports = new Map<string, [number, number]>();
+	for (const [from, to] of sent.teleports) {
+		teleports.set(from.join(), to);
+		teleports.set(to.join(), from);
+	}
+	return {
+		size: sent.size,
+		walls: new Set(sent.walls.map(wall => wall.join())),
+		teleports,
+		pairs: sent.teleports
+	};
+}
+
 const SNAKE_COLORS = [
 	"blue",
 	"green",
@@ -46,11 +82,20 @@ export const SnakeGame: React.FC<SnakeGameProps> = props => {
 	const ref = useRef<HTMLCanvasElement>(null);
 	const orderedKeys = useMemo(() => Object.keys(props.all_users), []);
 	const socket = useRef<WebSocket | undefined>(undefined);
+	const directionSeq = useRef(0);
+	const lastTick = useRef<number | undefined>(undefined);
+	// read while drawing, which happens outside of react renders
+	const board = useRef<Board>(toBoard({size: DEFAULT_BOARD_SIZE, walls: [], teleports: []}));
+	const [boardSize, setBoardSize] = useState<[number, number]>(DEFAULT_BOARD_SIZE);
+	// once sudden death starts the arena closes in and nobody can be revived
+	const [suddenDeath, setSuddenDeath] = useState(false);
 
 	const [readyStates, setReadyStates] = useState<ReadyStatus[] | 'started'>([]);
 	const [recentPowerups, setRecentPowerups] = useState<RecentPowerUp[]>([]);
 
 	const [gameStartedAt, setGameStartedAt] = useState(0);
+	// the game may live on another server than the one we were first sent to
+	const [url, setUrl] = useState(props.url);
 
 	function send(msg: ClientMessage) {
 		socket.current?.send(JSON.stringify(msg));
@@ -59,12 +104,13 @@ export const SnakeGame: React.FC<SnakeGameProps> = props => {
 	const [dead, setDead] = useState<{reviveLeft: number, reviveTotal: number} | false>(false);
 	
 	const [gameOver, setGameOver] = useState<false | {
-		winner: string,
+		winner: string | null,
+		result: MatchResult,
 		amounts_spent: AmountSpent[]
 	}>(false);
 
 	useEffect(() => {
-		const ws = new WebSocket(props.url);
+		const ws = new WebSocket(url);
 		socket.current = ws;
 
 		ws.addEventListener('open', () => {
@@ -81,19 +127,36 @@ export const SnakeGame: React.FC<SnakeGameProps> = props => {
 			// console.log(msg);
 
 			if (msg.type === "GameState") {
+				lastTick.current = msg.tick;
 				const g = ref.current?.getContext("2d");
 				if (g) {
-					g.clearRect(0, 0, 1000, 500);
+					const {size, walls, teleports, pairs} = board.current;
+					g.clearRect(0, 0, size[0] * CELL_SIZE, size[1] * CELL_SIZE);
+
+					g.fillStyle = "dimgray";
+					for (const wall of walls) {
+						const [x, y] = wall.split(",").map(Number);
+						g.fillRect(x * CELL_SIZE, y * CELL_SIZE, CELL_SIZE, CELL_SIZE);
+					}
+
+					// both ends of a teleport share a color
+					pairs.forEach((ends, index) => {
+						g.strokeStyle = TELEPORT_COLORS[index % TELEPORT_COLORS.length];
+						for (const [x, y] of ends) {
+							g.strokeRect(x * CELL_SIZE + 1, y * CELL_SIZE + 1, CELL_SIZE - 2, CELL_SIZE - 2);
+						}
+					});
+					g.strokeStyle = "black";
 
 					g.fillStyle = "red";
 					for (const apple of msg.apples) {
-						g.fillRect(apple[0] * 10, apple[1] * 10, 10, 10);
+						g.fillRect(apple[0] * CELL_SIZE, apple[1] * CELL_SIZE, CELL_SIZE, CELL_SIZE);
 					}
 
 					for (const snake of msg.snakes) {
 						if (snake.type === "Alive") {
 							g.fillStyle = SNAKE_COLORS[orderedKeys.indexOf(snake.user_id)];
-							g.strokeText(props.all_users[snake.user_id], snake.head[0] * 10 - 25, snake.head[1] * 10 + 40, 50);
+							g.strokeText(props.all_users[snake.user_id], snake.head[0] * CELL_SIZE - 25, snake.head[1] * CELL_SIZE + 40, 50);
 						}
 					}
 
@@ -119,15 +182,17 @@ export const SnakeGame: React.FC<SnakeGameProps> = props => {
 								else throw new Error("Unknown direction");
 
 								for (let i = 0; i < len; i++) {
-									g.fillRect(head[0] * 10, head[1] * 10, 10, 10);
-									head = [(head[0] + deltaHead[0] + 100) % 100, (head[1] + deltaHead[1] + 50) % 50];
+									g.fillRect(head[0] * CELL_SIZE, head[1] * CELL_SIZE, CELL_SIZE, CELL_SIZE);
+									// a body block on a teleport came out of it, so the body continues behind the other end
+									const from = teleports.get(head.join()) ?? head;
+									head = [(from[0] + deltaHead[0] + size[0]) % size[0], (from[1] + deltaHead[1] + size[1]) % size[1]];
 								}
 							}
 
 							g.fillStyle = SNAKE_COLORS[orderedKeys.indexOf(snake.user_id)];
 							if (snake.has_extra_life) g.fillStyle = "magenta";
 							else if (snake.frozen) g.fillStyle = "gray";
-							g.fillRect(snake.head[0] * 10, snake.head[1] * 10, 10, 10);
+							g.fillRect(snake.head[0] * CELL_SIZE, snake.head[1] * CELL_SIZE, CELL_SIZE, CELL_SIZE);
 						}
 					}
 				}
@@ -135,14 +200,27 @@ export const SnakeGame: React.FC<SnakeGameProps> = props => {
 				if (msg.recent_power_ups.length > 0) {
 					setRecentPowerups(recentPowerups => [...recentPowerups, ...msg.recent_power_ups]);
 				}
+			} else if (msg.type === "ConnectionError" && msg.error.type === "GameOnOtherNode") {
+				setUrl(msg.error.url);
+			} else if (msg.type === "Ping") {
+				send({type: "Pong", id: msg.id, client_time_ms: Date.now()});
 			} else if (msg.type === 'ReadyStatus') {
 				setReadyStates(msg.status);
 			} else if (msg.type === "StartGame") {
+				board.current = toBoard(msg.board);
+				setBoardSize(msg.board.size);
 				setReadyStates('started');
 				setGameStartedAt(Date.now());
+			} else if (msg.type === "SuddenDeath") {
+				setSuddenDeath(true);
+			} else if (msg.type === "ArenaShrunk") {
+				// drawn with the other walls on the next game state
+				for (const wall of msg.walls) {
+					board.current.walls.add(wall.join());
+				}
 			} else if (msg.type === "GameOver") {
 				setGameOver(msg);
-				props.onOver({results: msg.amounts_spent, winner: msg.winner});
+				props.onOver({results: msg.amounts_spent, winner: msg.winner, result: msg.result});
 			}
 		})
 
@@ -154,22 +232,30 @@ export const SnakeGame: React.FC<SnakeGameProps> = props => {
 			if (ev.code === 'ArrowUp') {
 				send({
 					type: "SetDirection",
-					direction: {type: "Up"}
+					direction: {type: "Up"},
+					seq: ++directionSeq.current,
+					tick: lastTick.current
 				})
 			} else if (ev.code === 'ArrowDown') {
 				send({
 					type: "SetDirection",
-					direction: {type: "Down"}
+					direction: {type: "Down"},
+					seq: ++directionSeq.current,
+					tick: lastTick.current
 				})
 			} else if (ev.code === 'ArrowLeft') {
 				send({
 					type: "SetDirection",
-					direction: {type: "Left"}
+					direction: {type: "Left"},
+					seq: ++directionSeq.current,
+					tick: lastTick.current
 				})
 			} else if (ev.code === 'ArrowRight') {
 				send({
 					type: "SetDirection",
-					direction: {type: "Right"}
+					direction: {type: "Right"},
+					seq: ++directionSeq.current,
+					tick: lastTick.current
 				})
 			}
 		};
@@ -183,7 +269,7 @@ export const SnakeGame: React.FC<SnakeGameProps> = props => {
 			document.body.removeEventListener('keydown', evList);
 		}
 
-	}, []);
+	}, [url]);
 
 	console.log(props);
 
@@ -229,7 +315,7 @@ export const SnakeGame: React.FC<SnakeGameProps> = props => {
 										}}
 									>
 										<h1 style={{fontSize: '3rem', fontWeight: 'bold', marginBottom: '20px'}}>Game Over!</h1>
-										<h2 style={{fontSize: '2rem', marginBottom: '20px'}}>Winner: <b>{props.all_users[gameOver.winner]}</b></h2>
+										<h2 style={{fontSize: '2rem', marginBottom: '20px'}}>{gameOver.result.type === "WinningTeam" ? <>Winner: <b>{gameOver.result.team}</b></> : gameOver.winner === null ? <b>Draw!</b> : <>Winner: <b>{props.all_users[gameOver.winner]}</b></>}</h2>
 										<h2 style={{fontSize: '2rem', marginBottom: '20px'}}>Prize: <u>{props.prize}</u></h2>
 
 										<div
@@ -272,7 +358,15 @@ export const SnakeGame: React.FC<SnakeGameProps> = props => {
 								)
 							}
 
-							<canvas width="1000" height="500" style={{gridArea: "game", width: '100%', imageRendering: 'pixelated', border: '1px solid black'}} ref={ref}>
+							{
+								suddenDeath && !gameOver && (
+									<div style={{gridArea: "game", alignSelf: 'start', zIndex: 10, marginTop: 10, background: 'darkred', color: 'white', fontWeight: 'bold', borderRadius: 5, padding: '5px 15px'}}>
+										Sudden death! The arena is closing in and there are no more revives
+									</div>
+								)
+							}
+
+							<canvas width={boardSize[0] * CELL_SIZE} height={boardSize[1] * CELL_SIZE} style={{gridArea: "game", width: '100%', imageRendering: 'pixelated', border: '1px solid black'}} ref={ref}>
 
 							</canvas>
 
@@ -391,9 +485,9 @@ export const SnakeGame: React.FC<SnakeGameProps> = props => {
 												border: 'none',
 												cursor: 'pointer',
 												marginTop: '10px',
-												opacity: dead.reviveLeft <= 0 ? 0.5 : 1,
+												opacity: dead.reviveLeft <= 0 || suddenDeath ? 0.5 : 1,
 											}}
-											disabled={dead.reviveLeft <= 0}
+											disabled={dead.reviveLeft <= 0 || suddenDeath}
 										>
 											Revive
 										</button>
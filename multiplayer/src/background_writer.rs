@@ -0,0 +1,56 @@
+use tokio::sync::{mpsc, oneshot};
+use tracing::error;
+
+type WriteJob = Box<dyn FnOnce() -> Result<(), String> + Send>;
+
+enum Write {
+    Job(WriteJob),
+    /// answered once every write sent before it is done
+    Flush(oneshot::Sender<()>),
+}
+
+/// Does blocking disk writes one at a time on a blocking thread, in the order they were sent, so
+/// a slow disk never holds up a game tick or anyone waiting on a lock
+#[derive(Debug, Clone)]
+pub struct BackgroundWriter {
+    /// what is being written, for the logs
+    name: &'static str,
+    sender: mpsc::UnboundedSender<Write>,
+}
+
+impl BackgroundWriter {
+    pub fn start(name: &'static str) -> BackgroundWriter {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Write>();
+        tokio::spawn(async move {
+            while let Some(write) = receiver.recv().await {
+                match write {
+                    Write::Job(job) => match tokio::task::spawn_blocking(job).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(err)) => error!(writer = name, %err, "Could not write"),
+                        Err(err) => error!(writer = name, %err, "Writing panicked"),
+                    },
+                    Write::Flush(done) => {
+                        let _ = done.send(());
+                    }
+                }
+            }
+        });
+        BackgroundWriter { name, sender }
+    }
+
+    /// Runs `job` once everything sent before it has been written
+    pub fn write(&self, job: impl FnOnce() -> Result<(), String> + Send + 'static) {
+        // the writer task only stops once the runtime does
+        if self.sender.send(Write::Job(Box::new(job))).is_err() {
+            error!(writer = self.name, "Writer has stopped, dropping write");
+        }
+    }
+
+    /// Waits for every write so far to be done
+    pub async fn flush(&self) {
+        let (done, wait) = oneshot::channel();
+        if self.sender.send(Write::Flush(done)).is_ok() {
+            let _ = wait.await;
+        }
+    }
+}
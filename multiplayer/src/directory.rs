@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tokio::time;
+use tracing::{debug, warn};
+use crate::fs_util::replace_file;
+use crate::games_server::all_games_state::AllGamesState;
+use crate::games_server::all_games_state::game_state::GamePhase;
+use crate::management_server::unix_time_millis;
+
+/// How often each node tells the directory how it is doing
+pub const ANNOUNCE_EVERY: Duration = Duration::from_secs(5);
+/// nodes that haven't announced themselves for this long are treated as gone
+const NODE_EXPIRES_AFTER_MS: u64 = 15_000;
+
+/// One server in the deployment, as last announced
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NodeInfo {
+    pub node_id: String,
+    /// http address other nodes and clients reach this node at, like `http://10.0.0.5:3001`
+    pub url: String,
+    /// games waiting for players or being played
+    pub games: usize,
+    pub draining: bool,
+    /// unix time in milliseconds
+    pub announced_at_ms: u64,
+}
+
+impl NodeInfo {
+    /// Where players connect to play games on this node
+    pub fn game_url(&self) -> String {
+        game_url(&self.url)
+    }
+
+    pub fn is_live(&self, now_ms: u64) -> bool {
+        now_ms.saturating_sub(self.announced_at_ms) < NODE_EXPIRES_AFTER_MS
+    }
+}
+
+/// Where players connect to play games on the node at http address `url`
+pub fn game_url(url: &str) -> String {
+    let url = match url.split_once("://") {
+        Some(("https", rest)) => format!("wss://{rest}"),
+        Some((_, rest)) => format!("ws://{rest}"),
+        None => format!("ws://{url}"),
+    };
+    format!("{}/game", url.trim_end_matches('/'))
+}
+
+/// Keeps track of which node each game lives on, and how loaded each node is, so games can be
+/// spread across several servers
+pub trait GameDirectory: Send + Sync {
+    /// Records how a node is doing, replacing what it last announced
+    fn announce(&self, node: &NodeInfo) -> Result<(), String>;
+
+    /// Every node that has ever announced itself, including ones that have since gone away
+    fn nodes(&self) -> Result<Vec<NodeInfo>, String>;
+
+    fn assign(&self, game_id: &str, node_id: &str) -> Result<(), String>;
+
+    /// The node `game_id` lives on, if it is known
+    fn find(&self, game_id: &str) -> Result<Option<String>, String>;
+}
+
+/// A directory only this process can see, for running a single node
+#[derive(Debug, Default)]
+pub struct LocalDirectory {
+    nodes: Mutex<HashMap<String, NodeInfo>>,
+    games: Mutex<HashMap<String, String>>,
+}
+
+impl GameDirectory for LocalDirectory {
+    fn announce(&self, node: &NodeInfo) -> Result<(), String> {
+        self.nodes.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(node.node_id.to_string(), node.clone());
+        Ok(())
+    }
+
+    fn nodes(&self) -> Result<Vec<NodeInfo>, String> {
+        Ok(self.nodes.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).values().cloned().collect())
+    }
+
+    fn assign(&self, game_id: &str, node_id: &str) -> Result<(), String> {
+        self.games.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(game_id.to_string(), node_id.to_string());
+        Ok(())
+    }
+
+    fn find(&self, game_id: &str) -> Result<Option<String>, String> {
+        Ok(self.games.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).get(game_id).cloned())
+    }
+}
+
+/// A directory kept in a folder every node can reach, like a shared volume. Each node only ever
+/// writes its own file and the files of games placed on it, so nodes never overwrite each other.
+#[derive(Debug)]
+pub struct FileDirectory {
+    path: PathBuf,
+}
+
+impl FileDirectory {
+    pub fn new(path: PathBuf) -> Result<FileDirectory, String> {
+        for dir in [path.join("nodes"), path.join("games")] {
+            std::fs::create_dir_all(&dir).map_err(|err| format!("could not create {}: {err}", dir.display()))?;
+        }
+        Ok(FileDirectory { path })
+    }
+
+}
+
+/// Ids end up in file names, so anything that could leave the directory is refused
+fn check_id(id: &str) -> Result<(), String> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.') || id.starts_with('.') {
+        return Err(format!("invalid id {id:?}"));
+    }
+    Ok(())
+}
+
+impl GameDirectory for FileDirectory {
+    fn announce(&self, node: &NodeInfo) -> Result<(), String> {
+        check_id(&node.node_id)?;
+        let json = serde_json::to_vec(node).map_err(|err| format!("could not serialize node: {err}"))?;
+        replace_file(&self.path.join("nodes").join(format!("{}.json", node.node_id)), &json)
+    }
+
+    fn nodes(&self) -> Result<Vec<NodeInfo>, String> {
+        let dir = self.path.join("nodes");
+        let entries = std::fs::read_dir(&dir).map_err(|err| format!("could not read {}: {err}", dir.display()))?;
+        let mut nodes = vec!();
+        for entry in entries {
+            let path = entry.map_err(|err| err.to_string())?.path();
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+            let bytes = std::fs::read(&path).map_err(|err| format!("could not read {}: {err}", path.display()))?;
+            nodes.push(serde_json::from_slice(&bytes).map_err(|err| format!("could not parse {}: {err}", path.display()))?);
+        }
+        Ok(nodes)
+    }
+
+    fn assign(&self, game_id: &str, node_id: &str) -> Result<(), String> {
+        check_id(game_id)?;
+        replace_file(&self.path.join("games").join(game_id), node_id.as_bytes())
+    }
+
+    fn find(&self, game_id: &str) -> Result<Option<String>, String> {
+        check_id(game_id)?;
+        let path = self.path.join("games").join(game_id);
+        match std::fs::read_to_string(&path) {
+            Ok(node_id) => Ok(Some(node_id.trim().to_string())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(format!("could not read {}: {err}", path.display())),
+        }
+    }
+}
+
+/// Runs a directory call on the blocking pool, a directory can be files on a shared volume that
+/// take a while to read
+async fn call_directory<T: Send + 'static>(
+    state: &Arc<AllGamesState>,
+    call: impl FnOnce(&dyn GameDirectory) -> Result<T, String> + Send + 'static
+) -> Result<T, String> {
+    let state = Arc::clone(state);
+    tokio::task::spawn_blocking(move || call(state.directory.as_ref()))
+        .await
+        .map_err(|err| format!("directory call panicked: {err}"))?
+}
+
+/// How this node is doing right now
+pub async fn this_node(state: &AllGamesState) -> NodeInfo {
+    let mut games = 0;
+    for shard in state.games.shards() {
+        games += shard.read().await.values()
+            .filter(|game| game.status.borrow().phase != GamePhase::GameOver)
+            .count();
+    }
+    NodeInfo {
+        node_id: state.node_id.to_string(),
+        url: state.public_url.to_string(),
+        games,
+        draining: state.shutdown.is_draining(),
+        announced_at_ms: unix_time_millis(),
+    }
+}
+
+/// Tells the directory how this node is doing every few seconds, for as long as the server runs
+pub async fn announce_node(state: Arc<AllGamesState>) {
+    let mut interval = time::interval(ANNOUNCE_EVERY);
+    loop {
+        interval.tick().await;
+        let node = this_node(&state).await;
+        if let Err(err) = call_directory(&state, move |directory| directory.announce(&node)).await {
+            warn!(%err, "Could not announce node");
+        }
+    }
+}
+
+/// Another node a new game should be created on because it has fewer games, `None` when this
+/// node is as good a place as any
+pub async fn place_game(state: &Arc<AllGamesState>) -> Option<NodeInfo> {
+    let this = this_node(state).await;
+    let nodes = match call_directory(state, |directory| directory.nodes()).await {
+        Ok(nodes) => nodes,
+        Err(err) => {
+            warn!(%err, "Could not list nodes, creating the game here");
+            return None;
+        }
+    };
+    nodes.into_iter()
+        .filter(|node| node.node_id != this.node_id && node.is_live(this.announced_at_ms) && !node.draining)
+        .min_by_key(|node| node.games)
+        .filter(|node| node.games < this.games)
+}
+
+/// Records that `game_id` lives on this node
+pub async fn assign_game(
+    state: &Arc<AllGamesState>,
+    game_id: &str
+) {
+    let (assigned_id, node_id) = (game_id.to_string(), state.node_id.to_string());
+    let assigned = call_directory(state, move |directory| directory.assign(&assigned_id, &node_id)).await;
+    if let Err(err) = assigned {
+        warn!(%game_id, %err, "Could not add game to the directory");
+    }
+}
+
+/// The node `game_id` lives on, when that is another node that is still up
+pub async fn find_other_node(
+    state: &Arc<AllGamesState>,
+    game_id: &str
+) -> Option<NodeInfo> {
+    let lookup = game_id.to_string();
+    let node_id = match call_directory(state, move |directory| directory.find(&lookup)).await {
+        Ok(node_id) => node_id?,
+        Err(err) => {
+            debug!(%game_id, %err, "Could not look up game");
+            return None;
+        }
+    };
+    if node_id == state.node_id {
+        return None;
+    }
+    let now = unix_time_millis();
+    match call_directory(state, |directory| directory.nodes()).await {
+        Ok(nodes) => nodes.into_iter().find(|node| node.node_id == node_id && node.is_live(now)),
+        Err(err) => {
+            warn!(%err, "Could not list nodes");
+            None
+        }
+    }
+}
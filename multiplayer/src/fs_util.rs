@@ -0,0 +1,21 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Writes `contents` to `path`, replacing the previous file only once the new one is complete, so
+/// readers and a crash part way through never leave half a file behind. Creates the directory if
+/// needed.
+pub fn replace_file(path: &Path, contents: &[u8]) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|err| format!("could not create {}: {err}", dir.display()))?;
+    }
+    let temp_path = temp_path(path);
+    std::fs::write(&temp_path, contents).map_err(|err| format!("could not write {}: {err}", temp_path.display()))?;
+    std::fs::rename(&temp_path, path).map_err(|err| format!("could not replace {}: {err}", path.display()))
+}
+
+/// `path` with `.tmp` added, keeping its extension so it is never mistaken for a finished file
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name: OsString = path.as_os_str().into();
+    name.push(".tmp");
+    name.into()
+}
@@ -4,5 +4,24 @@ pub mod client_message;
 pub mod server_message;
 pub mod all_games_state;
 pub mod client_connection;
+pub mod game_registry;
 pub mod game_runner;
-mod overlap_detector;
\ No newline at end of file
+pub mod game_workers;
+pub mod game_rules;
+pub mod collision_rules;
+pub mod board;
+pub mod sudden_death;
+pub mod win_conditions;
+pub mod teams;
+pub mod match_stats;
+pub mod occupancy_grid;
+pub mod errors;
+pub mod lag_compensation;
+pub mod latency;
+pub mod presence;
+mod rate_limiter;
+pub mod shutdown;
+pub mod snapshots;
+pub mod tick_scheduler;
+mod overlap_detector;
+mod spawn_finder;
\ No newline at end of file
@@ -1,17 +1,31 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use bytes::Bytes;
-use tokio::sync::{broadcast, mpsc, RwLock};
-use game_state::GameState;
+use std::sync::atomic::AtomicBool;
+use metrics_exporter_prometheus::PrometheusHandle;
+use tokio::sync::{broadcast, mpsc, watch};
+use game_state::GameStatus;
+use crate::background_writer::BackgroundWriter;
+use crate::directory::GameDirectory;
 use crate::games_server::client_message::ClientMessage;
+use crate::games_server::game_registry::GameRegistry;
+use crate::games_server::game_workers::GameWorkers;
+use crate::games_server::presence::Presence;
 use crate::games_server::server_message::ServerMessage;
+use crate::games_server::shutdown::Shutdown;
+use crate::games_server::tick_scheduler::MissedTickPolicy;
+use crate::management_server::match_history::MatchHistory;
+use crate::management_server::ratings::RatingStore;
 
 pub mod game_state;
 
 #[derive(Clone, Debug)]
 pub struct GameIncomingMessage {
     pub player_id: String,
-    pub message: ClientMessage
+    pub message: ClientMessage,
+    /// smoothed round trip time of the player's connection when it was sent, `None` until the
+    /// player has answered a ping
+    pub rtt_ms: Option<u64>
 }
 
 #[derive(Clone, Debug)]
@@ -23,12 +37,36 @@ pub struct GameOutgoingMessage {
 pub struct AuthGameState {
     /// map of access tokens to player ids
     pub players: HashMap<String, String>,
-    /// 
+    /// every message sent by the game runner, each connection only forwards the ones addressed to its player
     pub to_players: broadcast::Receiver<GameOutgoingMessage>,
-    pub game: Arc<Mutex<GameState>>,
+    pub status: watch::Receiver<GameStatus>,
     pub sender: mpsc::Sender<GameIncomingMessage>,
+    pub presence: Arc<Mutex<Presence>>,
 }
 
 pub struct AllGamesState {
-    pub games: RwLock<HashMap<String, AuthGameState>>
+    /// where ratings, match history and game snapshots are kept
+    pub data_dir: PathBuf,
+    pub games: GameRegistry,
+    /// where game simulations run
+    pub game_workers: GameWorkers,
+    /// ratings of every player across all games, updated as each game ends
+    pub ratings: Mutex<RatingStore>,
+    pub ratings_writer: BackgroundWriter,
+    /// every finished game
+    pub match_history: Mutex<MatchHistory>,
+    pub snapshot_writer: BackgroundWriter,
+    pub shutdown: Shutdown,
+    /// renders everything recorded with the `metrics` macros
+    pub metrics: PrometheusHandle,
+    /// set once the server is listening for connections
+    pub listener_bound: AtomicBool,
+    /// what games do about ticks they fall behind on
+    pub missed_tick_policy: MissedTickPolicy,
+    /// identifies this server among every node in the directory
+    pub node_id: String,
+    /// where other nodes and clients reach this server
+    pub public_url: String,
+    /// which node every game lives on
+    pub directory: Box<dyn GameDirectory>,
 }
@@ -1,5 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use serde::{Deserialize, Serialize};
+use crate::games_server::board::Board;
+use crate::games_server::lag_compensation::RecentMove;
+use crate::games_server::match_stats::{MatchStats, PlayerStats};
+use crate::games_server::occupancy_grid::OccupancyGrid;
+use crate::games_server::win_conditions::MatchResult;
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 #[derive(Clone)]
@@ -20,6 +25,20 @@ impl Direction {
             Direction::Right => Direction::Left,
         }
     }
+
+    /// Moves `point` one block in this direction, wrapping around the board edges
+    pub fn step(&self, point: (u32, u32), board_size: (u32, u32)) -> (u32, u32) {
+        let delta: (i32, i32) = match self {
+            Direction::Up => (0, -1),
+            Direction::Down => (0, 1),
+            Direction::Left => (-1, 0),
+            Direction::Right => (1, 0),
+        };
+        (
+            (point.0 as i32 + delta.0 + board_size.0 as i32) as u32 % board_size.0,
+            (point.1 as i32 + delta.1 + board_size.1 as i32) as u32 % board_size.1
+        )
+    }
 }
 
 #[derive(Debug)]
@@ -27,11 +46,66 @@ pub struct AliveSnake {
     pub user_id: String,
     pub head: (u32, u32),
     pub head_direction: Direction,
+    /// turns the player asked for that haven't been made yet with the `seq` they were sent with,
+    /// one is taken each time the snake moves
+    pub pending_directions: VecDeque<(Direction, Option<u64>)>,
+    /// the latest moves, oldest first, so a turn that arrives late can be replayed
+    pub recent_moves: VecDeque<RecentMove>,
     /// Direction to continue the snake plus the number of blocks in that direction
     pub blocks: Vec<(Direction, u32)>,
     pub invulnerable_for: Option<u64>,
     pub frozen_for: Option<u64>,
-    pub has_extra_life: bool
+    pub has_extra_life: bool,
+    /// goes up whenever the blocks change other than by moving forward, so the occupancy grid
+    /// knows the snake has to be walked again
+    pub generation: u64
+}
+
+impl AliveSnake {
+    /// A snake with its head on `head` heading the way of its first block, with nothing queued
+    /// and no power ups
+    pub fn new(user_id: &str, head: (u32, u32), blocks: Vec<(Direction, u32)>) -> AliveSnake {
+        AliveSnake {
+            user_id: user_id.to_string(),
+            head,
+            head_direction: blocks[0].0.clone(),
+            pending_directions: VecDeque::new(),
+            recent_moves: VecDeque::new(),
+            blocks,
+            invulnerable_for: None,
+            frozen_for: None,
+            has_extra_life: false,
+            generation: 0,
+        }
+    }
+
+    /// Adds `amount` blocks to the tail, they are filled in as the snake moves
+    pub fn grow(&mut self, amount: u32) {
+        if let Some(tail) = self.blocks.last_mut() {
+            tail.1 += amount;
+        }
+        self.generation += 1;
+    }
+
+    /// Takes `amount` blocks off the tail, never leaving the snake shorter than 3 blocks
+    pub fn shrink(&mut self, amount: u32) {
+        let mut amt_to_shrink = amount;
+        while amt_to_shrink > 0 && self.blocks.len() > 1 {
+            let block_len = self.blocks.len();
+            // a block that is used up entirely has to go, moving never expects an empty one
+            if self.blocks[block_len - 1].1 <= amt_to_shrink {
+                amt_to_shrink -= self.blocks[block_len - 1].1;
+                self.blocks.pop();
+            } else {
+                self.blocks[block_len - 1].1 -= amt_to_shrink;
+                amt_to_shrink = 0;
+            }
+        }
+        if amt_to_shrink > 0 && self.blocks.len() == 1 {
+            self.blocks[0].1 = std::cmp::max(3, self.blocks[0].1 as i32 - amt_to_shrink as i32) as u32;
+        }
+        self.generation += 1;
+    }
 }
 
 #[derive(Debug)]
@@ -41,18 +115,93 @@ pub enum Snake {
 }
 
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum GameState {
     WaitingForPlayers {
         ready_status: HashMap<String, bool>
     },
     Playing {
+        board: Board,
+        /// ticks since the game started
+        tick: u64,
+        /// how many rings of the arena have turned into walls, `None` until sudden death starts
+        rings_closed: Option<u32>,
         snakes: HashMap<String, Snake>,
+        /// which snakes are on each block, only current while it is being kept in sync with `snakes`
+        occupancy: OccupancyGrid,
         apples: Vec<(u32, u32)>,
+        stats: MatchStats,
         /// tracks how much each user id has spent
-        amounts_spent: HashMap<String, f64>
+        amounts_spent: HashMap<String, f64>,
+        /// user id to the highest `seq` of a direction change from them that was applied or refused
+        last_processed_seq: HashMap<String, u64>
     },
     GameOver {
-        winner: String,
+        result: MatchResult,
+        stats: Vec<PlayerStats>,
         amounts_spent: Vec<(String, f64)>
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GamePhase {
+    WaitingForPlayers,
+    Playing,
+    GameOver,
+}
+
+/// What can be seen of a game from outside its runner, published after every tick so nothing
+/// reading it ever holds up the game
+#[derive(Debug, Clone)]
+pub struct GameStatus {
+    pub phase: GamePhase,
+    /// ticks since the game started, 0 until it has
+    pub tick: u64,
+}
+
+impl From<&GameState> for GameStatus {
+    fn from(game: &GameState) -> GameStatus {
+        match game {
+            GameState::WaitingForPlayers { .. } => GameStatus { phase: GamePhase::WaitingForPlayers, tick: 0 },
+            GameState::Playing { tick, .. } => GameStatus { phase: GamePhase::Playing, tick: *tick },
+            GameState::GameOver { .. } => GameStatus { phase: GamePhase::GameOver, tick: 0 },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snake(blocks: Vec<(Direction, u32)>) -> AliveSnake {
+        AliveSnake::new("a", (10, 10), blocks)
+    }
+
+    #[test]
+    fn shrink_pops_a_tail_block_it_uses_up() {
+        let mut snake = snake(vec!((Direction::Up, 5), (Direction::Left, 10)));
+        snake.shrink(10);
+        assert_eq!(snake.blocks, vec!((Direction::Up, 5)));
+    }
+
+    #[test]
+    fn shrink_carries_on_into_the_next_block() {
+        let mut snake = snake(vec!((Direction::Up, 8), (Direction::Left, 4), (Direction::Down, 6)));
+        snake.shrink(10);
+        assert_eq!(snake.blocks, vec!((Direction::Up, 8)));
+    }
+
+    #[test]
+    fn shrink_takes_part_of_a_longer_tail_block() {
+        let mut snake = snake(vec!((Direction::Up, 5), (Direction::Left, 12)));
+        snake.shrink(10);
+        assert_eq!(snake.blocks, vec!((Direction::Up, 5), (Direction::Left, 2)));
+    }
+
+    #[test]
+    fn shrink_never_goes_below_three_blocks() {
+        let mut snake = snake(vec!((Direction::Up, 2), (Direction::Left, 5)));
+        snake.shrink(10);
+        assert_eq!(snake.blocks, vec!((Direction::Up, 3)));
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use serde::Deserialize;
+use crate::games_server::all_games_state::game_state::Direction;
+
+/// Folder map files are loaded from, each map is `<name>.toml`
+const MAPS_DIR: &str = "maps";
+
+pub const DEFAULT_BOARD_SIZE: (u32, u32) = (100, 50);
+
+/// The playing field of a game
+#[derive(Debug, Clone)]
+pub struct Board {
+    pub size: (u32, u32),
+    /// blocks that kill any snake that runs into them
+    pub walls: HashSet<(u32, u32)>,
+    /// a head moving onto one end of a teleport comes out of the other end
+    pub teleports: HashMap<(u32, u32), (u32, u32)>,
+}
+
+/// A map file, for example
+///
+/// ```toml
+/// name = "Portals"
+/// solid_borders = true
+/// layout = """
+/// ..........
+/// .A..##..B.
+/// ....##....
+/// .B......A.
+/// """
+/// ```
+///
+/// In the layout `.` is an empty block, `#` is a wall and any other character marks one end of a
+/// teleport, which must appear exactly twice.
+#[derive(Debug, Deserialize)]
+struct MapFile {
+    #[serde(default)]
+    solid_borders: bool,
+    layout: String,
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Self {
+            size: DEFAULT_BOARD_SIZE,
+            walls: HashSet::new(),
+            teleports: HashMap::new(),
+        }
+    }
+}
+
+impl Board {
+    /// Loads `maps/<name>.toml`
+    pub fn load(name: &str) -> Result<Board, String> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(format!("invalid map name {name:?}"));
+        }
+        let path = Path::new(MAPS_DIR).join(format!("{name}.toml"));
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|err| format!("could not read {}: {err}", path.display()))?;
+        Board::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Board, String> {
+        let map: MapFile = toml::from_str(contents).map_err(|err| err.to_string())?;
+
+        let rows: Vec<&str> = map.layout.lines().map(str::trim).filter(|row| !row.is_empty()).collect();
+        let width = rows.first().map(|row| row.chars().count()).unwrap_or(0);
+        if width == 0 || rows.iter().any(|row| row.chars().count() != width) {
+            return Err("layout rows must all be the same, non-zero width".to_string());
+        }
+        let size = (width as u32, rows.len() as u32);
+
+        let mut walls = HashSet::new();
+        let mut pads: HashMap<char, Vec<(u32, u32)>> = HashMap::new();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let point = (x as u32, y as u32);
+                match c {
+                    '.' => {},
+                    '#' => { walls.insert(point); },
+                    _ => pads.entry(c).or_default().push(point),
+                }
+            }
+        }
+
+        if map.solid_borders {
+            for x in 0..size.0 {
+                walls.insert((x, 0));
+                walls.insert((x, size.1 - 1));
+            }
+            for y in 0..size.1 {
+                walls.insert((0, y));
+                walls.insert((size.0 - 1, y));
+            }
+        }
+
+        let mut teleports = HashMap::new();
+        for (c, ends) in pads {
+            if ends.len() != 2 {
+                return Err(format!("teleport {c:?} must appear exactly twice"));
+            }
+            if ends.iter().any(|end| walls.contains(end)) {
+                return Err(format!("teleport {c:?} is inside a wall"));
+            }
+            teleports.insert(ends[0], ends[1]);
+            teleports.insert(ends[1], ends[0]);
+        }
+
+        Ok(Board { size, walls, teleports })
+    }
+
+    /// Where a head at `point` moving in `direction` ends up
+    pub fn step(&self, point: (u32, u32), direction: &Direction) -> (u32, u32) {
+        let next = direction.step(point, self.size);
+        if self.walls.contains(&next) {
+            return next;
+        }
+        match self.teleports.get(&next) {
+            Some(exit) => *exit,
+            None => next,
+        }
+    }
+
+    /// The block a snake's body continues into behind `point`, when that part of the body was
+    /// moving in `direction`. A body block on a teleport always came out of it, so the body
+    /// continues behind the other end.
+    pub fn step_back(&self, point: (u32, u32), direction: &Direction) -> (u32, u32) {
+        let from = match self.teleports.get(&point) {
+            Some(entrance) => *entrance,
+            None => point,
+        };
+        direction.opposite().step(from, self.size)
+    }
+
+    pub fn is_wall(&self, point: &(u32, u32)) -> bool {
+        self.walls.contains(point)
+    }
+
+    /// Whether something can be placed on this block, ignoring snakes
+    pub fn is_open(&self, point: &(u32, u32)) -> bool {
+        !self.walls.contains(point) && !self.teleports.contains_key(point)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_layout(layout: &str) -> Result<Board, String> {
+        Board::parse(&format!("layout = \"\"\"\n{layout}\n\"\"\""))
+    }
+
+    #[test]
+    fn parses_walls_and_teleports() {
+        let board = parse_layout("..........\n.A..##..B.\n....##....\n.B......A.").unwrap();
+        assert_eq!(board.size, (10, 4));
+        assert_eq!(board.walls, HashSet::from([(4, 1), (5, 1), (4, 2), (5, 2)]));
+        assert_eq!(board.teleports.get(&(1, 1)), Some(&(8, 3)));
+        assert_eq!(board.teleports.get(&(8, 3)), Some(&(1, 1)));
+        assert_eq!(board.teleports.get(&(8, 1)), Some(&(1, 3)));
+        assert!(board.is_wall(&(4, 1)));
+        assert!(!board.is_open(&(1, 1)));
+        assert!(board.is_open(&(0, 0)));
+    }
+
+    #[test]
+    fn rejects_malformed_maps() {
+        // a row reaching past the others would put walls off the board
+        assert!(parse_layout("....\n.....#\n....").is_err());
+        assert!(parse_layout("").is_err());
+        assert!(parse_layout("A...\n....").is_err());
+        assert!(parse_layout("A..A\n...A").is_err());
+        // solid borders would bury the teleport
+        assert!(Board::parse("solid_borders = true\nlayout = \"\"\"\nA...\n...A\n\"\"\"").is_err());
+        assert!(Board::parse("layout = 3").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_map_names() {
+        for name in ["", "../portals", "maps/portals", "portals.toml", "por tals"] {
+            let err = Board::load(name).unwrap_err();
+            assert!(err.starts_with("invalid map name"), "{name:?}: {err}");
+        }
+        assert!(Board::load("no_such_map").is_err());
+    }
+
+    #[test]
+    fn loads_the_portals_map() {
+        let board = Board::load("portals").unwrap();
+        assert_eq!(board.size, DEFAULT_BOARD_SIZE);
+        assert_eq!(board.teleports.len(), 4);
+        assert!(board.is_wall(&(0, 0)));
+        assert!(board.is_wall(&(99, 49)));
+        assert!(board.teleports.keys().all(|end| !board.is_wall(end)));
+    }
+
+    #[test]
+    fn step_back_undoes_step_through_a_teleport() {
+        let board = parse_layout("..........\n.A........\n..........\n........A.").unwrap();
+        for (start, direction) in [
+            ((0, 1), Direction::Right),
+            ((1, 0), Direction::Down),
+            ((8, 2), Direction::Down),
+            ((9, 3), Direction::Left),
+            ((5, 0), Direction::Up),
+        ] {
+            let next = board.step(start, &direction);
+            assert_eq!(board.step_back(next, &direction), start, "from {start:?} {direction:?}");
+        }
+        assert_eq!(board.step((0, 1), &Direction::Right), (8, 3));
+    }
+
+    #[test]
+    fn stepping_into_a_wall_stays_on_it() {
+        let board = parse_layout("..#.\n....").unwrap();
+        assert_eq!(board.step((1, 0), &Direction::Right), (2, 0));
+    }
+}
@@ -1,72 +1,309 @@
 use futures::{sink::SinkExt, stream::StreamExt};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use axum::extract::{State, WebSocketUpgrade};
-use axum::extract::ws::{Message, WebSocket};
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
 use axum::response::IntoResponse;
 use tokio::sync::{broadcast, mpsc};
-use bytes::Bytes;
-use futures::stream::SplitStream;
-use crate::games_server::all_games_state::{AllGamesState, GameIncomingMessage};
+use futures::stream::{SplitSink, SplitStream};
+use metrics::{counter, gauge, histogram};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{self, MissedTickBehavior};
+use tracing::{debug, error, info, info_span, trace, warn, Instrument};
+use crate::directory::find_other_node;
+use crate::games_server::all_games_state::{AllGamesState, GameIncomingMessage, GameOutgoingMessage};
 use crate::games_server::client_message::ClientMessage;
+use crate::games_server::errors::ConnectionError;
+use crate::games_server::latency::{LatencyTracker, PING_EVERY};
+use crate::games_server::presence::Presence;
+use crate::games_server::rate_limiter::RateLimiter;
 use crate::games_server::server_message::ServerMessage;
+use crate::logging::Redacted;
+use crate::management_server::unix_time_millis;
+use crate::server_metrics::{AUTH_FAILURES, BROADCAST_LAGGED, BROADCAST_SKIPPED_MESSAGES, CLIENTS_DISCONNECTED, CONNECTED_SOCKETS, MESSAGES_RECEIVED, MESSAGES_SENT, PLAYER_RTT, RATE_LIMITED_MESSAGES};
+
+/// How many replies from the connection itself, like errors, can be waiting to be sent
+const REPLY_BUFFER: usize = 16;
+/// Client messages are tiny, anything bigger than this is refused before it is read
+const MAX_MESSAGE_BYTES: usize = 4 * 1024;
 
 pub async fn handle_client_connection(
     ws: WebSocketUpgrade,
     State(state): State<Arc<AllGamesState>>
 ) -> impl IntoResponse {
-    println!("auth");
-    ws.on_upgrade(move |socket| handle_websocket_auth(socket, state))
+    ws.max_message_size(MAX_MESSAGE_BYTES)
+        .max_frame_size(MAX_MESSAGE_BYTES)
+        .on_upgrade(move |socket| handle_websocket_auth(socket, state))
+}
+
+/// Something the connection itself sends the player, rather than the game
+enum Reply {
+    Message(ServerMessage),
+    /// sends a close frame and ends the connection
+    Close { code: u16, reason: String },
+}
+
+/// Everything a connection needs from the game once its player has authenticated
+struct AuthenticatedPlayer {
+    game_id: String,
+    player_id: String,
+    to_players: broadcast::Receiver<GameOutgoingMessage>,
+    to_game: mpsc::Sender<GameIncomingMessage>,
+    presence: Arc<Mutex<Presence>>,
 }
 
 async fn handle_websocket_auth(
+    socket: WebSocket,
+    state: Arc<AllGamesState>
+) {
+    gauge!(CONNECTED_SOCKETS).increment(1);
+    authenticate_and_play(socket, state).await;
+    gauge!(CONNECTED_SOCKETS).decrement(1);
+}
+
+async fn authenticate_and_play(
     mut socket: WebSocket,
     state: Arc<AllGamesState>
 ) {
-    if let Some(Ok(msg)) = socket.recv().await {
-                println!("0");
-        if let Ok(msg) = serde_json::from_slice::<ClientMessage>(&*msg.into_data()) {
-            if let ClientMessage::Authenticate {access_token, game_id} = msg {
-                println!("a");
-                let games = state.games.read().await;
-                println!("b");
-                if let Some(game) = games.get(&game_id) {
-                println!("c");
-                    if let Some(player_id) = game.players.get(&access_token) {
-                println!("d");
-                        let player_id = player_id.clone();
-                        let (mut sender, receiver) = socket.split();
-
-                        let mut get_messages = game.to_players.resubscribe();
-                        
-                        let player_id_2 = player_id.clone();
-                        tokio::spawn(async move {
-                            while let Ok(msg) = get_messages.recv().await {
-                                if msg.to_player == player_id {
-                                    sender.send(Message::Binary(serde_json::to_vec(&msg.message).unwrap().into())).await.unwrap();
-                                }
-                            }
-                        });
-                        
-                        websocket_ready_handler(receiver, game.sender.clone(), player_id_2).await;
+    let player = match authenticate(&mut socket, &state).await {
+        Ok(player) => player,
+        Err(error @ ConnectionError::GameOnOtherNode { .. }) => {
+            info!(%error, "Sending player to another node");
+            if let Ok(message) = serde_json::to_vec(&ServerMessage::ConnectionError { error }) {
+                let _ = socket.send(Message::Binary(message.into())).await;
+            }
+            return;
+        }
+        Err(error) => {
+            counter!(AUTH_FAILURES, "reason" => error.reason()).increment(1);
+            warn!(%error, "Could not authenticate");
+            if let Ok(message) = serde_json::to_vec(&ServerMessage::ConnectionError { error }) {
+                // the connection is being dropped either way
+                let _ = socket.send(Message::Binary(message.into())).await;
+            }
+            return;
+        }
+    };
+
+    let span = info_span!("player", game_id = %player.game_id, player_id = %player.player_id);
+    span.in_scope(|| info!("Player connected"));
+    player.presence.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).connected(&player.player_id);
+
+    let (sender, receiver) = socket.split();
+    let (reply, replies) = mpsc::channel(REPLY_BUFFER);
+
+    tokio::spawn(
+        forward_to_player(sender, player.to_players, replies, player.player_id.clone()).instrument(span.clone())
+    );
+    websocket_ready_handler(receiver, player.to_game, reply, &player.presence, &player.player_id).instrument(span.clone()).await;
+
+    player.presence.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).disconnected(&player.player_id);
+
+    span.in_scope(|| info!("Player disconnected"));
+}
+
+/// Waits for the player to authenticate, the lock on the game's shard is only held while looking
+/// the player up
+async fn authenticate(
+    socket: &mut WebSocket,
+    state: &Arc<AllGamesState>
+) -> Result<AuthenticatedPlayer, ConnectionError> {
+    let Some(Ok(msg)) = socket.recv().await else {
+        return Err(ConnectionError::NotAuthenticated);
+    };
+    let msg = serde_json::from_slice::<ClientMessage>(&msg.into_data())
+        .map_err(|err| ConnectionError::InvalidMessage { reason: err.to_string() })?;
+    let ClientMessage::Authenticate {access_token, game_id} = msg else {
+        return Err(ConnectionError::NotAuthenticated);
+    };
+
+    let player = {
+        let games = state.games.shard(&game_id).read().await;
+        games.get(&game_id).map(|game| {
+            let Some(player_id) = game.players.get(&access_token) else {
+                debug!(%game_id, access_token = %Redacted(&access_token), "Unknown access token");
+                return Err(ConnectionError::InvalidAccessToken);
+            };
+            Ok(AuthenticatedPlayer {
+                game_id: game_id.to_string(),
+                player_id: player_id.clone(),
+                to_players: game.to_players.resubscribe(),
+                to_game: game.sender.clone(),
+                presence: Arc::clone(&game.presence),
+            })
+        })
+    };
+
+    match player {
+        Some(player) => player,
+        // the shard is no longer locked, asking the directory can take a while
+        None => Err(match find_other_node(state, &game_id).await {
+            Some(node) => ConnectionError::GameOnOtherNode { url: node.game_url() },
+            None => ConnectionError::UnknownGame,
+        }),
+    }
+}
+
+/// Sends the player everything the game addresses to them, and the connection's own replies,
+/// until either the game or the player's connection is gone
+async fn forward_to_player(
+    mut socket: SplitSink<WebSocket, Message>,
+    mut from_game: broadcast::Receiver<GameOutgoingMessage>,
+    mut replies: mpsc::Receiver<Reply>,
+    player_id: String
+) {
+    loop {
+        let message = tokio::select! {
+            msg = from_game.recv() => match msg {
+                Ok(msg) if msg.to_player == player_id => msg.message,
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    counter!(BROADCAST_LAGGED).increment(1);
+                    counter!(BROADCAST_SKIPPED_MESSAGES).increment(skipped);
+                    warn!(skipped, "Fell behind on messages from the game");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            reply = replies.recv() => match reply {
+                Some(Reply::Message(message)) => message,
+                Some(Reply::Close { code, reason }) => {
+                    let frame = CloseFrame { code, reason: reason.into() };
+                    if let Err(err) = socket.send(Message::Close(Some(frame))).await {
+                        debug!(%err, "Could not close connection");
                     }
+                    break;
                 }
+                // the player stopped reading, so nobody is listening anymore
+                None => break,
+            },
+        };
+
+        let bytes = match serde_json::to_vec(&message) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                error!(%err, "Could not serialize message");
+                continue;
             }
+        };
+        if let Err(err) = socket.send(Message::Binary(bytes.into())).await {
+            debug!(%err, "Could not send to player");
+            break;
         }
+        counter!(MESSAGES_SENT).increment(1);
     }
 }
 
 async fn websocket_ready_handler(
     mut socket: SplitStream<WebSocket>,
     sender: mpsc::Sender<GameIncomingMessage>,
-    player_id: String
+    reply: mpsc::Sender<Reply>,
+    presence: &Mutex<Presence>,
+    player_id: &str
 ) {
-    while let Some(Ok(msg)) = socket.next().await {
-        let msg = serde_json::from_slice::<ClientMessage>(&*msg.into_data());
-        if let Ok(msg) = msg {
-            sender.send(GameIncomingMessage {
-                player_id: player_id.to_string(),
-                message: msg,
-            }).await.unwrap();
+    let mut rate_limiter = RateLimiter::default();
+    // only tell the player about the first message in a row that was over the limit
+    let mut told_rate_limited = false;
+    let mut latency = LatencyTracker::default();
+    let mut ping_interval = time::interval(PING_EVERY);
+    ping_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        let msg = tokio::select! {
+            msg = socket.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            _ = ping_interval.tick() => {
+                let server_time_ms = unix_time_millis();
+                let id = latency.ping(server_time_ms);
+                // a ping can be skipped if the player is already behind on replies
+                let _ = reply.try_send(Reply::Message(ServerMessage::Ping { id, server_time_ms }));
+                continue;
+            }
+        };
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(err) => {
+                // includes messages over the size limit
+                counter!(CLIENTS_DISCONNECTED, "reason" => "protocol_error").increment(1);
+                debug!(%err, "Could not read from player");
+                break;
+            }
+        };
+        let data = match msg {
+            Message::Text(text) => text.into(),
+            Message::Binary(data) => data,
+            Message::Ping(_) | Message::Pong(_) => continue,
+            Message::Close(_) => break,
+        };
+        counter!(MESSAGES_RECEIVED).increment(1);
+
+        let msg = match serde_json::from_slice::<ClientMessage>(&data) {
+            Ok(msg) => msg,
+            Err(err) => {
+                debug!(%err, "Invalid message from player");
+                rate_limiter.reject();
+                if rate_limiter.is_abusive() {
+                    disconnect_abusive(&reply).await;
+                    break;
+                }
+                let error = ConnectionError::InvalidMessage { reason: err.to_string() };
+                // only fails once the player has disconnected
+                let _ = reply.send(Reply::Message(ServerMessage::ConnectionError { error })).await;
+                continue;
+            }
+        };
+        trace!(?msg, "Message from player");
+
+        if !rate_limiter.allow(&msg) {
+            counter!(RATE_LIMITED_MESSAGES, "message_type" => msg.name()).increment(1);
+            if rate_limiter.is_abusive() {
+                disconnect_abusive(&reply).await;
+                break;
+            }
+            if !told_rate_limited {
+                told_rate_limited = true;
+                let error = ConnectionError::RateLimited { message_type: msg.name().to_string() };
+                let _ = reply.send(Reply::Message(ServerMessage::ConnectionError { error })).await;
+            }
+            continue;
+        }
+        told_rate_limited = false;
+
+        // pongs are answered by the connection, the game never sees them
+        if let ClientMessage::Pong { id, client_time_ms } = msg {
+            if let Some(sample) = latency.pong(id, client_time_ms) {
+                histogram!(PLAYER_RTT).record(sample.rtt.as_secs_f64());
+                trace!(rtt_ms = sample.rtt_ms, clock_offset_ms = sample.clock_offset_ms, "Pong from player");
+                presence.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).set_rtt(player_id, sample.rtt_ms);
+                let _ = reply.send(Reply::Message(ServerMessage::Latency {
+                    rtt_ms: sample.rtt_ms,
+                    clock_offset_ms: sample.clock_offset_ms,
+                })).await;
+            }
+            continue;
+        }
+
+        let sent = sender.send(GameIncomingMessage {
+            player_id: player_id.to_string(),
+            message: msg,
+            rtt_ms: latency.rtt_ms(),
+        }).await;
+        if sent.is_err() {
+            debug!("Game stopped");
+            let _ = reply.send(Reply::Message(ServerMessage::ConnectionError { error: ConnectionError::GameStopped })).await;
+            break;
         }
     }
 }
+
+/// Tells a player who keeps sending messages that are turned away why they are being
+/// disconnected, and closes the connection
+async fn disconnect_abusive(reply: &mpsc::Sender<Reply>) {
+    counter!(CLIENTS_DISCONNECTED, "reason" => "rate_limited").increment(1);
+    warn!("Disconnecting player for sending too many messages");
+    let reason = "too many messages".to_string();
+    let error = ConnectionError::Disconnected { reason: reason.to_string() };
+    let _ = reply.send(Reply::Message(ServerMessage::ConnectionError { error })).await;
+    let _ = reply.send(Reply::Close { code: close_code::POLICY, reason }).await;
+}
@@ -1,12 +1,61 @@
+use std::fmt;
 use serde::Deserialize;
 use crate::games_server::all_games_state::game_state::Direction;
 use crate::games_server::power_ups::PowerUps;
+use crate::logging::Redacted;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Deserialize, Clone)]
 #[serde(tag = "type")]
 pub enum ClientMessage {
     Authenticate {access_token: String, game_id: String},
     UsePowerUp {power_up: PowerUps},
-    SetDirection {direction: Direction},
+    /// `seq` is counted up by the client with every direction it sends, and echoed back in
+    /// `GameState` once the server has dealt with it. `tick` is the tick of the last `GameState`
+    /// the client had when the player turned, used to make late turns when the game has lag
+    /// compensation on.
+    SetDirection {direction: Direction, #[serde(default)] seq: Option<u64>, #[serde(default)] tick: Option<u64>},
     SetReady {ready: bool},
+    /// answers [`Ping`](crate::games_server::server_message::ServerMessage::Ping) `id`,
+    /// `client_time_ms` being the client's unix time in milliseconds when it answered
+    Pong {id: u64, client_time_ms: u64},
+}
+
+impl ClientMessage {
+    /// The kind of message, for rate limits and metrics
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClientMessage::Authenticate { .. } => "Authenticate",
+            ClientMessage::UsePowerUp { .. } => "UsePowerUp",
+            ClientMessage::SetDirection { .. } => "SetDirection",
+            ClientMessage::SetReady { .. } => "SetReady",
+            ClientMessage::Pong { .. } => "Pong",
+        }
+    }
+}
+
+/// Written out by hand so access tokens never end up in the logs
+impl fmt::Debug for ClientMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientMessage::Authenticate { access_token, game_id } => f.debug_struct("Authenticate")
+                .field("access_token", &Redacted(access_token))
+                .field("game_id", game_id)
+                .finish(),
+            ClientMessage::UsePowerUp { power_up } => f.debug_struct("UsePowerUp")
+                .field("power_up", power_up)
+                .finish(),
+            ClientMessage::SetDirection { direction, seq, tick } => f.debug_struct("SetDirection")
+                .field("direction", direction)
+                .field("seq", seq)
+                .field("tick", tick)
+                .finish(),
+            ClientMessage::SetReady { ready } => f.debug_struct("SetReady")
+                .field("ready", ready)
+                .finish(),
+            ClientMessage::Pong { id, client_time_ms } => f.debug_struct("Pong")
+                .field("id", id)
+                .field("client_time_ms", client_time_ms)
+                .finish(),
+        }
+    }
 }
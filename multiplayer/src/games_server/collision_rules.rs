@@ -0,0 +1,224 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use serde::{Deserialize, Serialize};
+use crate::games_server::all_games_state::game_state::{AliveSnake, Snake};
+use crate::games_server::board::Board;
+use crate::games_server::occupancy_grid::OccupancyGrid;
+use crate::games_server::teams::Teams;
+
+/// What happens when two snakes run into each other head first
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(tag = "type")]
+pub enum HeadOnRule {
+    /// the shorter snake dies, if both are the same length both die
+    LongerSurvives,
+    #[default]
+    BothDie,
+    BothSurvive,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct CollisionRules {
+    /// whether a snake dies when its head runs into its own body
+    pub self_collision: bool,
+    pub head_on: HeadOnRule,
+    /// whether teammates can kill each other
+    pub friendly_fire: bool,
+}
+
+impl CollisionRules {
+    /// Works out which snakes die this tick, along with the snake that killed each of them, if
+    /// any. Every snake is checked against the same positions, so the result does not depend on
+    /// the order the snakes are stored in.
+    ///
+    /// Only the snakes on each head's block are looked at, so `occupancy` has to be in sync with
+    /// `snakes`.
+    pub fn find_dead(
+        &self,
+        snakes: &HashMap<String, Snake>,
+        occupancy: &OccupancyGrid,
+        board: &Board,
+        teams: &Teams
+    ) -> BTreeMap<String, Option<String>> {
+        let mut dead = BTreeMap::new();
+        for snake in snakes.values() {
+            let Snake::Alive(snake) = snake else {
+                continue;
+            };
+
+            // being invulnerable or frozen only protects from other snakes, so walls and the
+            // closing rings of sudden death still end a game
+            if board.is_wall(&snake.head) {
+                dead.insert(snake.user_id.to_string(), None);
+                continue;
+            }
+
+            if !is_vulnerable(snake) {
+                continue;
+            }
+
+            let Some(cells) = occupancy.body(&snake.user_id) else {
+                continue;
+            };
+            // if a snake runs into more than one other snake at once, the kill goes to the first by id
+            let mut on_head: Vec<&str> = occupancy.occupants(&snake.head).iter().map(|user_id| &**user_id).collect();
+            on_head.sort();
+            on_head.dedup();
+
+            for other_id in on_head {
+                if other_id == snake.user_id {
+                    if self.self_collision && occupancy.count(&snake.user_id, &snake.head) > 1 {
+                        dead.entry(snake.user_id.to_string()).or_insert(None);
+                    }
+                    continue;
+                }
+
+                if !self.friendly_fire && teams.are_teammates(&snake.user_id, other_id) {
+                    continue;
+                }
+
+                let (Some(Snake::Alive(other)), Some(other_cells)) = (snakes.get(other_id), occupancy.body(other_id)) else {
+                    continue;
+                };
+                if is_vulnerable(other) && is_head_on(cells, other_cells) {
+                    let dies = match self.head_on {
+                        HeadOnRule::LongerSurvives => cells.len() <= other_cells.len(),
+                        HeadOnRule::BothDie => true,
+                        HeadOnRule::BothSurvive => false,
+                    };
+                    if dies {
+                        dead.entry(snake.user_id.to_string()).or_insert(Some(other.user_id.to_string()));
+                    }
+                } else {
+                    // anything else on the head's block is the other snake's body
+                    dead.entry(snake.user_id.to_string()).or_insert(Some(other.user_id.to_string()));
+                }
+            }
+        }
+        dead
+    }
+}
+
+fn is_vulnerable(snake: &AliveSnake) -> bool {
+    snake.invulnerable_for.is_none() && snake.frozen_for.is_none()
+}
+
+/// Whether two snakes' heads are on the same block, or have just moved through each other
+fn is_head_on(
+    cells: &VecDeque<(u32, u32)>,
+    other_cells: &VecDeque<(u32, u32)>
+) -> bool {
+    if cells[0] == other_cells[0] {
+        return true;
+    }
+    match (cells.get(1), other_cells.get(1)) {
+        (Some(neck), Some(other_neck)) => *neck == other_cells[0] && *other_neck == cells[0],
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::games_server::all_games_state::game_state::Direction;
+
+    fn alive(user_id: &str, head: (u32, u32), blocks: Vec<(Direction, u32)>) -> (String, Snake) {
+        (user_id.to_string(), Snake::Alive(AliveSnake::new(user_id, head, blocks)))
+    }
+
+    fn find_dead(
+        rules: &CollisionRules,
+        snakes: Vec<(String, Snake)>,
+        teams: &Teams
+    ) -> BTreeMap<String, Option<String>> {
+        let board = Board::default();
+        let snakes: HashMap<String, Snake> = snakes.into_iter().collect();
+        let mut occupancy = OccupancyGrid::new(board.size);
+        occupancy.sync(&snakes, &board);
+        rules.find_dead(&snakes, &occupancy, &board, teams)
+    }
+
+    fn killed(entries: &[(&str, Option<&str>)]) -> BTreeMap<String, Option<String>> {
+        entries.iter().map(|(user_id, killer)| (user_id.to_string(), killer.map(str::to_string))).collect()
+    }
+
+    #[test]
+    fn running_into_a_body_kills_only_the_snake_that_ran_in() {
+        let dead = find_dead(&CollisionRules::default(), vec!(
+            alive("a", (5, 5), vec!((Direction::Up, 3))),
+            alive("b", (6, 5), vec!((Direction::Right, 3))),
+        ), &Teams::default());
+        assert_eq!(dead, killed(&[("a", Some("b"))]));
+    }
+
+    #[test]
+    fn head_on_rules() {
+        let snakes = || vec!(
+            alive("a", (5, 5), vec!((Direction::Right, 3))),
+            alive("b", (5, 5), vec!((Direction::Left, 5))),
+        );
+        let rules = |head_on| CollisionRules { head_on, ..CollisionRules::default() };
+
+        assert_eq!(find_dead(&rules(HeadOnRule::BothDie), snakes(), &Teams::default()), killed(&[("a", Some("b")), ("b", Some("a"))]));
+        assert_eq!(find_dead(&rules(HeadOnRule::LongerSurvives), snakes(), &Teams::default()), killed(&[("a", Some("b"))]));
+        assert_eq!(find_dead(&rules(HeadOnRule::BothSurvive), snakes(), &Teams::default()), killed(&[]));
+    }
+
+    #[test]
+    fn snakes_passing_through_each_other_are_head_on() {
+        let dead = find_dead(&CollisionRules::default(), vec!(
+            alive("a", (6, 5), vec!((Direction::Right, 3))),
+            alive("b", (5, 5), vec!((Direction::Left, 3))),
+        ), &Teams::default());
+        assert_eq!(dead, killed(&[("a", Some("b")), ("b", Some("a"))]));
+    }
+
+    #[test]
+    fn self_collision_only_when_enabled() {
+        // heading up into the block it left three moves ago
+        let snakes = || vec!(alive("a", (5, 5), vec!(
+            (Direction::Up, 1), (Direction::Left, 1), (Direction::Down, 1), (Direction::Right, 2),
+        )));
+        assert_eq!(find_dead(&CollisionRules::default(), snakes(), &Teams::default()), killed(&[]));
+        let rules = CollisionRules { self_collision: true, ..CollisionRules::default() };
+        assert_eq!(find_dead(&rules, snakes(), &Teams::default()), killed(&[("a", None)]));
+    }
+
+    #[test]
+    fn teammates_only_collide_with_friendly_fire() {
+        let user_ids = vec!("a".to_string(), "b".to_string(), "c".to_string());
+        let teams = Teams::new(HashMap::from([
+            ("red".to_string(), vec!("a".to_string(), "b".to_string())),
+            ("blue".to_string(), vec!("c".to_string())),
+        ]), &user_ids).unwrap();
+        let snakes = || vec!(
+            alive("a", (5, 5), vec!((Direction::Up, 3))),
+            alive("b", (6, 5), vec!((Direction::Right, 3))),
+        );
+        assert_eq!(find_dead(&CollisionRules::default(), snakes(), &teams), killed(&[]));
+        let rules = CollisionRules { friendly_fire: true, ..CollisionRules::default() };
+        assert_eq!(find_dead(&rules, snakes(), &teams), killed(&[("a", Some("b"))]));
+    }
+
+    #[test]
+    fn result_does_not_depend_on_snake_order() {
+        // a and b meet head on while c and d run into their bodies
+        let snakes = || vec!(
+            alive("a", (10, 10), vec!((Direction::Right, 4))),
+            alive("b", (10, 10), vec!((Direction::Left, 4))),
+            alive("c", (8, 10), vec!((Direction::Down, 3))),
+            alive("d", (11, 10), vec!((Direction::Up, 3))),
+        );
+        let expected = killed(&[("a", Some("b")), ("b", Some("a")), ("c", Some("a")), ("d", Some("b"))]);
+        for rotation in 0..4 {
+            for reversed in [false, true] {
+                let mut order = snakes();
+                order.rotate_left(rotation);
+                if reversed {
+                    order.reverse();
+                }
+                assert_eq!(find_dead(&CollisionRules::default(), order, &Teams::default()), expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::fmt;
+use serde::Serialize;
+
+/// Why the game turned down something a player asked for. Nothing is charged when a power up
+/// is refused.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum GameError {
+    /// the target of a power up isn't in this game
+    UnknownPlayer { user_id: String },
+    /// the player has to be alive to do this
+    NotAlive,
+    /// only dead snakes can be revived
+    NotDead,
+    /// the target of a power up is dead
+    TargetNotAlive { user_id: String },
+    /// power ups can't be used on teammates
+    TargetIsTeammate { user_id: String },
+    /// extra lives and revives can't be bought once sudden death starts
+    SuddenDeath,
+    /// there is nowhere safe to put a revived snake right now
+    NoRoomToRevive,
+}
+
+impl fmt::Display for GameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GameError::UnknownPlayer { user_id } => write!(f, "{user_id} is not in this game"),
+            GameError::NotAlive => write!(f, "only alive snakes can do this"),
+            GameError::NotDead => write!(f, "only dead snakes can be revived"),
+            GameError::TargetNotAlive { user_id } => write!(f, "{user_id} is not alive"),
+            GameError::TargetIsTeammate { user_id } => write!(f, "{user_id} is a teammate"),
+            GameError::SuddenDeath => write!(f, "not allowed during sudden death"),
+            GameError::NoRoomToRevive => write!(f, "nowhere to revive"),
+        }
+    }
+}
+
+/// Why a player's connection refused a message, or was closed
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum ConnectionError {
+    /// the message couldn't be read
+    InvalidMessage { reason: String },
+    /// the first message on a connection has to be `Authenticate`
+    NotAuthenticated,
+    UnknownGame,
+    InvalidAccessToken,
+    /// the game isn't running anymore
+    GameStopped,
+    /// too many messages of this type, it was dropped
+    RateLimited { message_type: String },
+    /// the connection is being closed because of how it behaved
+    Disconnected { reason: String },
+    /// the game is running on another server, connect to `url` to play it
+    GameOnOtherNode { url: String },
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionError::InvalidMessage { reason } => write!(f, "invalid message: {reason}"),
+            ConnectionError::NotAuthenticated => write!(f, "not authenticated"),
+            ConnectionError::UnknownGame => write!(f, "unknown game"),
+            ConnectionError::InvalidAccessToken => write!(f, "invalid access token"),
+            ConnectionError::GameStopped => write!(f, "game stopped"),
+            ConnectionError::RateLimited { message_type } => write!(f, "too many {message_type} messages"),
+            ConnectionError::Disconnected { reason } => write!(f, "disconnected: {reason}"),
+            ConnectionError::GameOnOtherNode { url } => write!(f, "game is on another server at {url}"),
+        }
+    }
+}
+
+impl ConnectionError {
+    /// The label auth failures are counted under in the metrics
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ConnectionError::InvalidMessage { .. } => "invalid_message",
+            ConnectionError::NotAuthenticated => "not_authenticated",
+            ConnectionError::UnknownGame => "unknown_game",
+            ConnectionError::InvalidAccessToken => "unknown_access_token",
+            ConnectionError::GameStopped => "game_stopped",
+            ConnectionError::RateLimited { .. } => "rate_limited",
+            ConnectionError::Disconnected { .. } => "disconnected",
+            ConnectionError::GameOnOtherNode { .. } => "game_on_other_node",
+        }
+    }
+}
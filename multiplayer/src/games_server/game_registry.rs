@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use tokio::sync::RwLock;
+use crate::games_server::all_games_state::AuthGameState;
+
+/// How many locks the games are split between
+const SHARDS: usize = 32;
+
+/// Every game on the server, split into shards by game id so looking up one game only ever
+/// waits on the others in its shard
+pub struct GameRegistry {
+    shards: Vec<RwLock<HashMap<String, AuthGameState>>>,
+    hasher: RandomState,
+}
+
+impl Default for GameRegistry {
+    fn default() -> GameRegistry {
+        GameRegistry {
+            shards: (0..SHARDS).map(|_| RwLock::new(HashMap::new())).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+}
+
+impl GameRegistry {
+    /// Which shard the game is kept in
+    pub fn shard_index(&self, game_id: &str) -> usize {
+        self.hasher.hash_one(game_id) as usize % self.shards.len()
+    }
+
+    /// The shard the game is kept in, holding it locked only blocks games in the same shard
+    pub fn shard(&self, game_id: &str) -> &RwLock<HashMap<String, AuthGameState>> {
+        &self.shards[self.shard_index(game_id)]
+    }
+
+    pub fn shards(&self) -> &[RwLock<HashMap<String, AuthGameState>>] {
+        &self.shards
+    }
+
+    pub async fn insert(&self, game_id: String, game: AuthGameState) {
+        self.shard(&game_id).write().await.insert(game_id, game);
+    }
+}
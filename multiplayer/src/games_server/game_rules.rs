@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+use crate::games_server::collision_rules::CollisionRules;
+use crate::games_server::lag_compensation::LagCompensationRules;
+use crate::games_server::sudden_death::SuddenDeathRules;
+use crate::games_server::win_conditions::WinCondition;
+
+/// Everything about how a game is played that can be picked when the game is created
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct GameRules {
+    pub collisions: CollisionRules,
+    /// name of the map to play on, the default is an empty board that wraps around
+    pub map: Option<String>,
+    /// shrink the arena after a while so the game can't go on forever
+    pub sudden_death: Option<SuddenDeathRules>,
+    pub win_condition: WinCondition,
+    /// replay turns that reach the server late, off unless set
+    pub lag_compensation: Option<LagCompensationRules>,
+}
@@ -1,54 +1,137 @@
-use std::ptr::read;
-use std::sync::mpsc::{Receiver, Sender};
-use std::sync::{Arc, Mutex, MutexGuard};
-use std::task::ready;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::{broadcast, mpsc, watch};
+use metrics::{counter, gauge};
 use tokio::time;
+use tracing::{debug, debug_span, error, info, warn};
 use crate::games_server::all_games_state::{AllGamesState, GameIncomingMessage, GameOutgoingMessage};
-use crate::games_server::all_games_state::game_state::{AliveSnake, Direction, GameState, Snake};
+use crate::games_server::all_games_state::game_state::{AliveSnake, Direction, GameState, GameStatus, Snake};
+use crate::games_server::board::Board;
 use crate::games_server::client_message::ClientMessage;
-use crate::games_server::overlap_detector::detect_overlap;
+use crate::games_server::errors::GameError;
+use crate::games_server::game_rules::GameRules;
+use crate::games_server::lag_compensation::{RecentMove, MAX_REWIND_TICKS};
+use crate::games_server::match_stats::MatchStats;
+use crate::games_server::occupancy_grid::OccupancyGrid;
 use crate::games_server::power_up_cost_loader::PowerUpCosts;
 use crate::games_server::power_ups::{GetPowerUpCost, PowerUps};
-use crate::games_server::server_message::{AmountSpent, ReadyStatus, RecentPowerUp, ServerMessage};
+use crate::games_server::presence::Presence;
+use crate::games_server::server_message::{AmountSpent, GameEvent, ReadyStatus, RecentPowerUp, ServerMessage, Team, TeamAmountSpent};
+use crate::games_server::snapshots::{GameSnapshot, SnapshotPhase};
+use crate::games_server::spawn_finder::find_spawn_point;
+use crate::games_server::sudden_death::ring;
+use crate::games_server::teams::Teams;
+use crate::games_server::tick_scheduler::TickScheduler;
+use crate::games_server::win_conditions::{MatchResult, Standing};
+use crate::management_server::match_history::MatchRecord;
+use crate::management_server::{unix_time_millis, unix_time_secs};
+use crate::server_metrics::{INPUTS_DROPPED, LAG_COMPENSATED_TURNS, POWER_UPS_PURCHASED, POWER_UP_REVENUE};
 
-const TICK_TIME_MS: u64 = 100;
+pub const TICK_TIME_MS: u64 = 100;
 
-const BOARD_SIZE: (u32, u32) = (100, 50);
 const NUM_APPLES: u32 = 40;
 const MOVE_EVERY_TICKS: u32 = 1;
+const START_LENGTH: u32 = 3;
+/// most direction changes from one player handled in a single tick, only the newest are kept
+const MAX_TURNS_PER_TICK: usize = 4;
+/// how many turns a player can queue up ahead of their snake, more than this are ignored
+const MAX_QUEUED_DIRECTIONS: usize = 3;
+/// how often everyone is told how the other players' connections are doing
+const PRESENCE_EVERY_TICKS: u32 = 10;
+/// how often a game in progress is saved to disk, so it can be settled if the server crashes.
+/// Ticks where someone spends money are always saved straight away.
+const SNAPSHOT_EVERY_TICKS: u32 = 50;
+
+/// Everything about a game that is decided when it is created
+pub struct GameConfig {
+    pub game_id: String,
+    /// the fund group the game is played for, players are also rated within it
+    pub fund_id: Option<String>,
+    /// map of access tokens to player ids
+    pub access_tokens: HashMap<String, String>,
+    pub power_up_costs: PowerUpCosts,
+    pub rules: GameRules,
+    pub board: Board,
+    pub teams: Teams,
+}
 
 pub async fn game_runner(
-    game: Arc<Mutex<GameState>>,
+    status: watch::Sender<GameStatus>,
+    presence: Arc<Mutex<Presence>>,
     all_players: Vec<String>,
     mut get_from_players: mpsc::Receiver<GameIncomingMessage>,
-    mut send_to_players: broadcast::Sender<GameOutgoingMessage>,
+    send_to_players: broadcast::Sender<GameOutgoingMessage>,
     state: Arc<AllGamesState>,
-    power_up_costs: PowerUpCosts
+    config: GameConfig
 ) {
-    let mut interval = time::interval(Duration::from_millis(TICK_TIME_MS));
+    let GameConfig { game_id, fund_id, access_tokens, power_up_costs, rules, board, teams } = config;
 
-    let mut tick_count = 0;
+    let mut scheduler = TickScheduler::new(Duration::from_millis(TICK_TIME_MS), state.missed_tick_policy);
+    // only the runner ever touches the game, everything else sees the status it publishes
+    let mut game = GameState::WaitingForPlayers {
+        ready_status: all_players.iter().map(|user_id| (user_id.to_string(), false)).collect()
+    };
+
+    let mut tick_count: u32 = 0;
+    let mut started_at = 0;
+    let mut told_about_shutdown = false;
+    // the last tick a snake died or used its extra life, late turns aren't replayed past it
+    let mut last_collision_tick: Option<u64> = None;
     
     loop {
-        interval.tick().await;
+        scheduler.tick().await;
 
         let mut player_messages = vec!();
-
-        while !get_from_players.is_empty() {
-            let message = get_from_players.recv().await.unwrap();
+        while let Ok(message) = get_from_players.try_recv() {
             player_messages.push(message);
         }
 
-        let mut game = game.lock().unwrap();
+        // past the cap a player's oldest turns are dropped, so one player can't crowd out the
+        // others. Power ups are paid for, so they are never dropped.
+        let mut turns_this_tick: HashMap<String, usize> = HashMap::new();
+        for message in &player_messages {
+            if let ClientMessage::SetDirection { .. } = message.message {
+                *turns_this_tick.entry(message.player_id.to_string()).or_insert(0) += 1;
+            }
+        }
+        let mut dropped_turns = vec!();
+        player_messages.retain(|message| {
+            let ClientMessage::SetDirection { seq, .. } = message.message else {
+                return true;
+            };
+            let Some(turns) = turns_this_tick.get_mut(&message.player_id) else {
+                return true;
+            };
+            if *turns <= MAX_TURNS_PER_TICK {
+                return true;
+            }
+            *turns -= 1;
+            counter!(INPUTS_DROPPED).increment(1);
+            debug!(player_id = %message.player_id, message = ?message.message, "Dropped turn over the per tick cap");
+            dropped_turns.push((message.player_id.to_string(), seq));
+            false
+        });
+
+        let _tick_span = debug_span!("tick", tick = tick_count).entered();
 
         let mut send_to_all = vec!();
+        let mut send_to_player: Vec<(String, ServerMessage)> = vec!();
         
         let mut start_game = false;
-        let mut winner = None;
+        let mut result: Option<MatchResult> = None;
+        // spending can't be left for the next snapshot, it has to survive a crash
+        let mut spent = false;
+
+        let shutdown_deadline = state.shutdown.deadline();
+        if let Some(deadline) = shutdown_deadline && !told_about_shutdown {
+            told_about_shutdown = true;
+            send_to_all.push(ServerMessage::ServerShuttingDown {
+                deadline_secs: deadline.saturating_duration_since(time::Instant::now()).as_secs_f64().ceil() as u64,
+            });
+        }
         
-        match &mut *game {
+        match &mut game {
             GameState::WaitingForPlayers { ready_status } => {
                 for message in player_messages {
                     if let ClientMessage::SetReady { ready } = message.message {
@@ -63,113 +146,188 @@ pub async fn game_runner(
                         }).collect()}
                 );
 
-                if ready_status.values().all(|val| *val) {
+                // a lobby that hasn't started by the time the server drains is reopened after the restart
+                if ready_status.values().all(|val| *val) && shutdown_deadline.is_none() {
                     start_game = true;
                 }
             },
-            GameState::Playing { apples, snakes, amounts_spent } => {
+            GameState::Playing { board, tick, rings_closed, apples, stats, snakes, occupancy, amounts_spent, last_processed_seq } => {
+                let sudden_death = rules.sudden_death.as_ref()
+                    .is_some_and(|sudden_death| sudden_death.has_started(*tick, TICK_TIME_MS));
+
+                // turns that were dropped won't be made, so there is nothing to wait for
+                for (player_id, seq) in dropped_turns {
+                    ack_seq(last_processed_seq, &player_id, seq);
+                }
+
                 let mut power_ups_used = vec!();
+                let mut events = vec!();
                 for message in player_messages {
                     match message.message {
-                        ClientMessage::Authenticate { .. } => {},
+                        ClientMessage::Authenticate { .. } | ClientMessage::Pong { .. } => {},
                         ClientMessage::UsePowerUp { power_up, .. } => {
-                            let should_charge: bool = match &power_up {
-                                PowerUps::ExtraLife => {
-                                    if let Snake::Alive(snake) = snakes.get_mut(&message.player_id).unwrap() {
+                            let outcome: Result<(), GameError> = match &power_up {
+                                PowerUps::ExtraLife => match snakes.get_mut(&message.player_id) {
+                                    _ if sudden_death => Err(GameError::SuddenDeath),
+                                    Some(Snake::Alive(snake)) => {
                                         snake.has_extra_life = true;
-                                        true
-                                    } else {
-                                        false
+                                        Ok(())
                                     }
+                                    Some(Snake::Dead { .. }) => Err(GameError::NotAlive),
+                                    None => Err(GameError::UnknownPlayer { user_id: message.player_id.to_string() }),
                                 }
-                                PowerUps::AddLength => {
-                                    if let Snake::Alive(snake) = snakes.get_mut(&message.player_id).unwrap() {
-                                        let blocks_len = snake.blocks.len();
-                                        snake.blocks[blocks_len - 1].1 += 10;
-                                        true
-                                    } else { false }
+                                PowerUps::AddLength => match snakes.get_mut(&message.player_id) {
+                                    Some(Snake::Alive(snake)) => {
+                                        snake.grow(10);
+                                        Ok(())
+                                    }
+                                    Some(Snake::Dead { .. }) => Err(GameError::NotAlive),
+                                    None => Err(GameError::UnknownPlayer { user_id: message.player_id.to_string() }),
                                 }
-                                PowerUps::FreezeOpponent {opponent} => {
-                                    if let Snake::Alive(opponent) = snakes.get_mut(opponent).unwrap() {
+                                PowerUps::FreezeOpponent {opponent} => match snakes.get_mut(opponent) {
+                                    _ if teams.are_teammates(&message.player_id, opponent) => Err(GameError::TargetIsTeammate { user_id: opponent.to_string() }),
+                                    Some(Snake::Alive(opponent)) => {
                                         opponent.frozen_for = Some(3 * 1000 / TICK_TIME_MS);
-                                        true
-                                    } else { false }
+                                        Ok(())
+                                    }
+                                    Some(Snake::Dead { .. }) => Err(GameError::TargetNotAlive { user_id: opponent.to_string() }),
+                                    None => Err(GameError::UnknownPlayer { user_id: opponent.to_string() }),
                                 }
-                                PowerUps::ShrinkOpponent {opponent} => {
-                                    if let Snake::Alive(opponent) = snakes.get_mut(dbg!(opponent)).unwrap() {
-                                        let mut amt_to_shrink = 10;
-                                        while amt_to_shrink > 0 && opponent.blocks.len() > 1 {
-                                            let block_len = opponent.blocks.len();
-                                            if opponent.blocks[block_len - 1].1 < amt_to_shrink {
-                                                amt_to_shrink -= opponent.blocks[block_len - 1].1;
-                                                opponent.blocks.pop();
-                                            } else {
-                                                opponent.blocks[block_len - 1].1 -= amt_to_shrink;
-                                                amt_to_shrink = 0;
-                                            }
-                                        }
-                                        if amt_to_shrink > 0 && opponent.blocks.len() == 1 {
-                                            opponent.blocks[0].1 = std::cmp::max(3, (opponent.blocks[0].1 as i32 - amt_to_shrink as i32)) as u32;
-                                        }
-                                        true
-                                    } else {
-                                        false
+                                PowerUps::ShrinkOpponent {opponent} => match snakes.get_mut(opponent) {
+                                    _ if teams.are_teammates(&message.player_id, opponent) => Err(GameError::TargetIsTeammate { user_id: opponent.to_string() }),
+                                    Some(Snake::Alive(opponent)) => {
+                                        opponent.shrink(10);
+                                        Ok(())
                                     }
+                                    Some(Snake::Dead { .. }) => Err(GameError::TargetNotAlive { user_id: opponent.to_string() }),
+                                    None => Err(GameError::UnknownPlayer { user_id: opponent.to_string() }),
                                 }
-                                PowerUps::Revive => {
-                                    if let Snake::Dead { head, .. } = snakes.get(&message.player_id).unwrap() {
-                                        snakes.insert(message.player_id.to_string(), Snake::Alive(AliveSnake {
-                                            user_id: message.player_id.to_string(),
-                                            head: (BOARD_SIZE.0 / 2, BOARD_SIZE.1 / 2),
-                                            head_direction: Direction::Up,
-                                            blocks: vec![(Direction::Down, 3)],
-                                            invulnerable_for: Some(3 * 1000 / TICK_TIME_MS),
-                                            frozen_for: None,
-                                            has_extra_life: false,
-                                        }));
-                                        true
-                                    } else {
-                                        false
+                                PowerUps::Revive => match snakes.get(&message.player_id) {
+                                    _ if sudden_death => Err(GameError::SuddenDeath),
+                                    Some(Snake::Dead { .. }) => {
+                                        occupancy.sync(snakes, board);
+                                        // only charge for a revive if there is somewhere safe to put the snake
+                                        if let Some(spawn) = find_spawn_point(board, occupancy, snakes.values(), apples, START_LENGTH) {
+                                            events.push(GameEvent::Revived {
+                                                user_id: message.player_id.to_string(),
+                                                head: spawn.head,
+                                            });
+                                            let mut snake = AliveSnake::new(&message.player_id, spawn.head, vec![(spawn.direction, START_LENGTH)]);
+                                            snake.invulnerable_for = Some(3 * 1000 / TICK_TIME_MS);
+                                            snakes.insert(message.player_id.to_string(), Snake::Alive(snake));
+                                            Ok(())
+                                        } else {
+                                            Err(GameError::NoRoomToRevive)
+                                        }
                                     }
+                                    Some(Snake::Alive(_)) => Err(GameError::NotDead),
+                                    None => Err(GameError::UnknownPlayer { user_id: message.player_id.to_string() }),
                                 }
                             };
-                            if should_charge {
+                            if let Err(error) = outcome {
+                                debug!(player_id = %message.player_id, ?power_up, %error, "Power up refused");
+                                send_to_player.push((message.player_id.to_string(), ServerMessage::GameError { error }));
+                            } else {
+                                info!(player_id = %message.player_id, ?power_up, cost = power_up_costs.get_cost(&power_up), "Power up used");
+                                counter!(POWER_UPS_PURCHASED, "power_up" => power_up.name()).increment(1);
+                                gauge!(POWER_UP_REVENUE, "power_up" => power_up.name()).increment(power_up_costs.get_cost(&power_up));
                                 amounts_spent.insert(message.player_id.to_string(), amounts_spent.get(&message.player_id).unwrap_or(&0.0) + power_up_costs.get_cost(&power_up));
+                                spent = true;
+                                events.push(GameEvent::PowerUpUsed {
+                                    user_id: message.player_id.to_string(),
+                                    power_up: power_up.clone(),
+                                    target: match &power_up {
+                                        PowerUps::ShrinkOpponent { opponent } | PowerUps::FreezeOpponent { opponent } => Some(opponent.to_string()),
+                                        _ => None,
+                                    },
+                                });
                                 power_ups_used.push(RecentPowerUp {
                                     user_id: message.player_id.to_string(),
                                     power_up,
                                 });
                             }
                         }
-                        ClientMessage::SetDirection { direction } => {
-                            if let Snake::Alive(snake) = snakes.get_mut(&message.player_id).unwrap() {
-                                if snake.head_direction != direction && snake.head_direction.opposite() != direction {
-                                    snake.head_direction = direction;
+                        ClientMessage::SetDirection { direction, seq, tick: seen_tick } => {
+                            // someone dying since may have depended on where the snake was, so
+                            // turns are only replayed if nobody has
+                            if let Some(lag_compensation) = &rules.lag_compensation
+                                && let Some(seen_tick) = seen_tick
+                                && last_collision_tick.is_none_or(|collided| collided <= seen_tick)
+                                // the state took half the round trip to reach the player and the
+                                // turn the other half, so it can't be from any further back
+                                && let Some(rtt_ms) = message.rtt_ms {
+                                occupancy.sync(snakes, board);
+                                if let Some(moves) = lag_compensation.compensate_turn(&message.player_id, &direction, seen_tick, *tick, rtt_ms.div_ceil(TICK_TIME_MS), snakes, occupancy, apples, board) {
+                                    counter!(LAG_COMPENSATED_TURNS).increment(1);
+                                    debug!(player_id = %message.player_id, moves, "Late turn replayed");
+                                    events.push(GameEvent::TurnCompensated {
+                                        user_id: message.player_id.to_string(),
+                                        moves,
+                                    });
+                                    ack_seq(last_processed_seq, &message.player_id, seq);
+                                    continue;
                                 }
                             }
+
+                            // checked against the direction the snake will be going once every
+                            // turn already queued has been made
+                            if let Some(Snake::Alive(snake)) = snakes.get_mut(&message.player_id)
+                                && snake.pending_directions.len() < MAX_QUEUED_DIRECTIONS {
+                                let current = snake.pending_directions.back().map(|(direction, _)| direction).unwrap_or(&snake.head_direction);
+                                if *current != direction && current.opposite() != direction {
+                                    snake.pending_directions.push_back((direction, seq));
+                                    continue;
+                                }
+                            }
+                            // refused, so there is nothing to wait for
+                            ack_seq(last_processed_seq, &message.player_id, seq);
                         }
                         ClientMessage::SetReady { .. } => {}
                     }
                 }
                 
-                // add apples
-                while (apples.len() as u32) < NUM_APPLES {
+                // close in the arena
+                if let Some(sudden_death_rules) = &rules.sudden_death
+                    && sudden_death {
+                    if rings_closed.is_none() {
+                        for snake in snakes.values_mut() {
+                            if let Snake::Dead { ticks_to_revive, .. } = snake {
+                                *ticks_to_revive = None;
+                            }
+                        }
+                        send_to_all.push(ServerMessage::SuddenDeath);
+                    }
+
+                    let closed = rings_closed.get_or_insert(0);
+                    let mut new_walls = vec!();
+                    while *closed < sudden_death_rules.rings_closed(*tick, TICK_TIME_MS) {
+                        for cell in ring(board.size, *closed) {
+                            if board.walls.insert(cell) {
+                                new_walls.push(cell);
+                            }
+                        }
+                        *closed += 1;
+                    }
+                    if !new_walls.is_empty() {
+                        apples.retain(|apple| !board.is_wall(apple));
+                        send_to_all.push(ServerMessage::ArenaShrunk { walls: new_walls });
+                    }
+                }
+
+                // catch up with everything but moves, which the grid follows as they happen
+                occupancy.sync(snakes, board);
+
+                // add apples, a crowded map may not have room for all of them this tick
+                for _ in apples.len() as u32..NUM_APPLES {
                     for _ in 0..5 {
                         let random_coords = (
-                            rand::random::<u32>() % BOARD_SIZE.0,
-                            rand::random::<u32>() % BOARD_SIZE.1
+                            rand::random::<u32>() % board.size.0,
+                            rand::random::<u32>() % board.size.1
                         );
 
-                        let mut overlap = false;
-                        
-                        for snake in snakes.values() {
-                            if let Snake::Alive(snake) = snake {
-                                if detect_overlap(&random_coords, snake) {
-                                    overlap = true;
-                                    break;
-                                }
-                            }
-                        }
+                        let overlap = !board.is_open(&random_coords)
+                            || apples.contains(&random_coords)
+                            || occupancy.is_occupied(&random_coords);
                         
                         if !overlap {
                             apples.push(random_coords);
@@ -178,53 +336,55 @@ pub async fn game_runner(
                     }
                 }
                 
+                let in_game_before = teams.group(standings(snakes, stats));
+
                 // detect death
-                let mut dead = vec!();
-                for (user_id, snake) in snakes.iter() {
-                    if let Snake::Alive(snake) = snake {
-                        if snake.invulnerable_for.is_none() && snake.frozen_for.is_none() {
-                            for (other_id, snake_id) in snakes.iter() {
-                                if other_id == user_id {
-                                    continue;
-                                } else if let Snake::Alive(other_snake) = snake_id {
-                                    if detect_overlap(&snake.head, &other_snake) {
-                                        dead.push(user_id.to_string());
-                                        break;
-                                    }
-                                }
-                            }
-                        }
-                    }
+                let dead = rules.collisions.find_dead(snakes, occupancy, board, &teams);
+                if !dead.is_empty() {
+                    last_collision_tick = Some(*tick);
                 }
-                for dead_snake in dead {
-                    let snake = snakes.get_mut(&dead_snake).unwrap();
+                for (dead_snake, killer) in dead {
+                    let Some(snake) = snakes.get_mut(&dead_snake) else {
+                        continue;
+                    };
                     if let Snake::Alive(alive_snake) = snake {
                         if alive_snake.has_extra_life {
                             alive_snake.has_extra_life = false;
                             alive_snake.invulnerable_for = Some(3 * 1000 / TICK_TIME_MS);
+                            events.push(GameEvent::ExtraLifeUsed {
+                                user_id: dead_snake,
+                                killed_by: killer,
+                            });
                         } else {
+                            debug!(player_id = %dead_snake, killed_by = ?killer, "Snake died");
+                            stats.died(&dead_snake, killer.as_ref());
+                            // turns still queued will never be made
+                            for (_, seq) in &alive_snake.pending_directions {
+                                ack_seq(last_processed_seq, &dead_snake, *seq);
+                            }
+                            events.push(GameEvent::Died {
+                                user_id: dead_snake.to_string(),
+                                killed_by: killer,
+                            });
                             *snake = Snake::Dead {
                                 user_id: alive_snake.user_id.to_string(),
                                 head: alive_snake.head,
-                                ticks_to_revive: Some(100),
+                                ticks_to_revive: if sudden_death { None } else { Some(100) },
                             }
                         }
                     }
                 }
                 
                 // detect apples being eaten
-                apples.retain(|apple| {
-                    for (_, snake) in snakes.iter_mut() {
-                        if let Snake::Alive(snake) = snake {
-                            if snake.head == *apple {
-                                let blocks_len = snake.blocks.len();
-                                snake.blocks[blocks_len - 1].1 += 2;
-                                return false;
-                            }
-                        }
-                    }
-                    true
-                });
+                let mut just_ate_apple = vec!();
+                for (user_id, apple) in eat_apples(snakes, apples) {
+                    stats.ate_apple(&user_id);
+                    just_ate_apple.push(user_id.to_string());
+                    events.push(GameEvent::AteApple {
+                        user_id,
+                        apple,
+                    });
+                }
 
                 // toggle invulnerability
                 for (_, snake) in snakes.iter_mut() {
@@ -243,46 +403,45 @@ pub async fn game_runner(
                                 snake.frozen_for = None;
                             }
                         }
-                    } else if let Snake::Dead {ticks_to_revive, user_id, .. } = snake {
-                        if let Some(remaining) = ticks_to_revive {
-                            if *remaining == 0 {
-                                *ticks_to_revive = None;
+                    } else if let Snake::Dead {ticks_to_revive, .. } = snake
+                        && let Some(remaining) = ticks_to_revive {
+                        if *remaining == 0 {
+                            *ticks_to_revive = None;
 
-                            } else {
-                                *remaining -= 1;
-                            }
+                        } else {
+                            *remaining -= 1;
                         }
                     }
                 }
 
-                let alive: Vec<_> = snakes.iter().filter(|(_, snake)| {
-                    if let Snake::Dead { ticks_to_revive, .. } = snake {
-                        ticks_to_revive.is_some()
-                    } else {
-                        true
-                    }
-                }).collect();
-                if alive.len() == 1 {
-                    winner = Some(alive[0].0.to_string());
-                } else if alive.len() == 0 {
-                    winner = Some(snakes.iter().next().unwrap().0.to_string());
-                }
+                stats.tick(snakes, TICK_TIME_MS);
+
+                let in_game = teams.group(standings(snakes, stats));
+                result = rules.win_condition.check(*tick, TICK_TIME_MS, &in_game, &in_game_before)
+                    .map(|decision| teams.result(decision));
 
                 // move snake forward
-                if tick_count % MOVE_EVERY_TICKS == 0 {
+                if tick_count.is_multiple_of(MOVE_EVERY_TICKS) {
                     for (_, snake) in snakes.iter_mut() {
                         if let Snake::Alive(snake) = snake {
                             if snake.frozen_for.is_some() {
                                 continue;
                             }
 
-                            let head_delta: (i32, i32) = match snake.head_direction {
-                                Direction::Up => (0, -1),
-                                Direction::Down => (0, 1),
-                                Direction::Left => (-1, 0),
-                                Direction::Right => (1, 0),
-                            };
-                            snake.head = ((snake.head.0 as i32 + head_delta.0 + BOARD_SIZE.0 as i32) as u32 % BOARD_SIZE.0, (snake.head.1 as i32 + head_delta.1 + BOARD_SIZE.1 as i32) as u32 % BOARD_SIZE.1);
+                            if snake.recent_moves.len() as u64 == MAX_REWIND_TICKS {
+                                snake.recent_moves.pop_front();
+                            }
+                            snake.recent_moves.push_back(RecentMove {
+                                tick: *tick,
+                                head_before: snake.head,
+                                length: snake.blocks.iter().map(|(_, len)| len).sum(),
+                            });
+
+                            if let Some((direction, seq)) = snake.pending_directions.pop_front() {
+                                snake.head_direction = direction;
+                                ack_seq(last_processed_seq, &snake.user_id, seq);
+                            }
+                            snake.head = board.step(snake.head, &snake.head_direction);
 
                             if snake.head_direction != snake.blocks[0].0 {
                                 snake.blocks.insert(0, (snake.head_direction.clone(), 0));
@@ -293,61 +452,174 @@ pub async fn game_runner(
                             if snake.blocks[blocks_len - 1].1 == 0 {
                                 snake.blocks.pop();
                             }
+                            occupancy.moved(snake, board);
                         }
                     }
                 }
                 
+                if !events.is_empty() {
+                    send_to_all.push(ServerMessage::Events { events });
+                }
                 send_to_all.push(
                     ServerMessage::GameState {
+                        tick: *tick,
+                        server_time_ms: unix_time_millis(),
+                        last_processed_seq: last_processed_seq.clone(),
                         snakes: snakes.values().map(|snake| snake.into()).collect(),
                         apples: apples.clone(),
-                        just_ate_apple: vec!(),
+                        just_ate_apple,
                         recent_power_ups: power_ups_used,
+                        stats: stats.all(),
                     }
-                )
+                );
+
+                *tick += 1;
             },
-            GameState::GameOver { amounts_spent, winner } => {
+            GameState::GameOver { amounts_spent, result, stats } => {
                 send_to_all.push(
                     ServerMessage::GameOver {
-                        winner: winner.to_string(),
+                        winner: result.winner().cloned(),
+                        result: result.clone(),
                         amounts_spent: amounts_spent.iter().map(|(user_id, amount)| AmountSpent {
                             user_id: user_id.to_string(),
                             amount_spent: *amount,
-                        }).collect()
+                        }).collect(),
+                        team_amounts_spent: teams.amounts_spent(amounts_spent).into_iter().map(|(team, amount)| TeamAmountSpent {
+                            team,
+                            amount_spent: amount,
+                        }).collect(),
+                        stats: stats.clone(),
                     }
                 )
             }
         }
         
         if start_game {
+            info!(players = ?all_players, "Game started");
+            state.shutdown.game_started();
             let num_players = all_players.len();
-            *game = GameState::Playing {
+            let mut snakes = HashMap::new();
+            let mut occupancy = OccupancyGrid::new(board.size);
+            for (idx, key) in all_players.iter().enumerate() {
+                occupancy.sync(&snakes, &board);
+                let (head, direction) = match find_spawn_point(&board, &occupancy, snakes.values(), &[], START_LENGTH) {
+                    Some(spawn) => (spawn.head, spawn.direction),
+                    None => ((board.size.0 * idx as u32 / num_players as u32 + board.size.0 / (2 * num_players as u32), board.size.1 / 2), Direction::Up),
+                };
+                snakes.insert(key.to_string(), Snake::Alive(AliveSnake::new(key, head, vec![(direction, START_LENGTH)])));
+            }
+            occupancy.sync(&snakes, &board);
+            send_to_all.push(ServerMessage::StartGame {
+                board: (&board).into(),
+                teams: teams.members().into_iter().map(|(team, user_ids)| Team { team, user_ids }).collect(),
+            });
+            started_at = unix_time_secs();
+            game = GameState::Playing {
+                board: board.clone(),
+                tick: 0,
+                rings_closed: None,
                 apples: vec!(),
-                snakes: all_players.iter().enumerate().map(|(idx, key)| (key.to_string(), Snake::Alive(AliveSnake {
-                    user_id: key.to_string(),
-                    head: (BOARD_SIZE.0 * idx as u32 / num_players as u32 + BOARD_SIZE.0 / (2 * num_players as u32), BOARD_SIZE.1 / 2),
-                    head_direction: Direction::Up,
-                    blocks: vec![(Direction::Up, 3)],
-                    invulnerable_for: None,
-                    frozen_for: None,
-                    has_extra_life: false,
-                }))).collect(),
+                stats: MatchStats::new(&all_players),
+                snakes,
+                occupancy,
                 amounts_spent: all_players.iter().map(|key| (key.to_string(), 0.0)).collect(),
+                last_processed_seq: HashMap::new(),
             };
-
-            send_to_all.push(ServerMessage::StartGame);
         }
 
-        if let Some(winner) = winner {
-            *game = GameState::GameOver {
-                winner,
-                amounts_spent: match &*game {
-                    GameState::Playing { amounts_spent, .. } => amounts_spent.iter().map(|(user_id, amount)| (user_id.to_string(), *amount)).collect(),
-                    _ => panic!("Game over but not playing"),
+        // a result is only ever decided while playing
+        if let Some(result) = result
+            && let GameState::Playing { amounts_spent, stats, tick, .. } = &game {
+            info!(?result, "Game over");
+            let amounts_spent: Vec<_> = amounts_spent.iter().map(|(user_id, amount)| (user_id.to_string(), *amount)).collect();
+            let stats = stats.all();
+            let duration_ms = *tick * TICK_TIME_MS;
+
+            // only the ratings are worked out here, writing them is left to the writer
+            let save = state.ratings.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+                .record_game(&game_id, fund_id.as_ref(), &result, &stats, &teams);
+            match save {
+                Ok(save) => state.ratings_writer.write(move || save.write()),
+                Err(err) => error!(%err, "Could not save ratings"),
+            }
+
+            let record = MatchRecord {
+                game_id: game_id.to_string(),
+                fund_id: fund_id.clone(),
+                user_ids: all_players.clone(),
+                teams: teams.members(),
+                rules: rules.clone(),
+                result: result.clone(),
+                started_at,
+                finished_at: unix_time_secs(),
+                duration_ms,
+                amounts_spent: amounts_spent.iter().map(|(user_id, amount)| AmountSpent {
+                    user_id: user_id.to_string(),
+                    amount_spent: *amount,
+                }).collect(),
+                stats: stats.clone(),
+                replay: None,
+            };
+            // only the history in memory is updated here, writing it is left to the writer
+            let save = state.match_history.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).record(record);
+            if let Err(err) = &save {
+                error!(%err, "Could not save match");
+            }
+            // the snapshot only goes once the match is on disk, so a crash in between still
+            // settles the game on restart
+            let save = save.ok();
+            let data_dir = state.data_dir.clone();
+            let finished_game_id = game_id.to_string();
+            state.snapshot_writer.write(move || {
+                if let Some(save) = save {
+                    save.write()?;
                 }
+                GameSnapshot::remove(&data_dir, &finished_game_id)
+            });
+            state.shutdown.game_finished();
+
+            game = GameState::GameOver {
+                result,
+                stats,
+                amounts_spent,
             }
         }
         
+        let cut_off = shutdown_deadline.is_some_and(|deadline| time::Instant::now() >= deadline);
+        let phase = match &game {
+            GameState::WaitingForPlayers { .. } if tick_count == 0 => Some(SnapshotPhase::Lobby),
+            GameState::Playing { tick, amounts_spent, stats, .. } if start_game || cut_off || spent || tick_count.is_multiple_of(SNAPSHOT_EVERY_TICKS) => {
+                Some(SnapshotPhase::Playing {
+                    tick: *tick,
+                    started_at,
+                    amounts_spent: amounts_spent.iter().map(|(user_id, amount)| AmountSpent {
+                        user_id: user_id.to_string(),
+                        amount_spent: *amount,
+                    }).collect(),
+                    stats: stats.all(),
+                })
+            }
+            _ => None,
+        };
+        if let Some(phase) = phase {
+            let snapshot = GameSnapshot {
+                game_id: game_id.to_string(),
+                fund_id: fund_id.clone(),
+                user_ids: all_players.clone(),
+                access_tokens: access_tokens.clone(),
+                rules: rules.clone(),
+                teams: teams.members(),
+                phase,
+            };
+            let data_dir = state.data_dir.clone();
+            state.snapshot_writer.write(move || snapshot.save(&data_dir));
+        }
+
+        if tick_count.is_multiple_of(PRESENCE_EVERY_TICKS) {
+            let players = presence.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).players(&all_players);
+            send_to_all.push(ServerMessage::Presence { players });
+        }
+
         for message in send_to_all {
             for player in &all_players {
                 let result = send_to_players.send(GameOutgoingMessage {
@@ -355,11 +627,129 @@ pub async fn game_runner(
                     message: message.clone(),
                 });
                 if let Err(err) = result {
-                    eprintln!("Error sending message to player: {}", err);
+                    debug!(%player, %err, "Could not send message to player");
                 }
             }
         }
+        for (player, message) in send_to_player {
+            if let Err(err) = send_to_players.send(GameOutgoingMessage { to_player: player.to_string(), message }) {
+                debug!(%player, %err, "Could not send message to player");
+            }
+        }
+
+        status.send_replace(GameStatus::from(&game));
+
+        // once draining, only games still being played keep running, until they are cut off
+        match &game {
+            GameState::Playing { .. } if cut_off => {
+                warn!("Game cut off by shutdown");
+                state.shutdown.game_finished();
+                break;
+            }
+            GameState::Playing { .. } => {},
+            GameState::WaitingForPlayers { .. } | GameState::GameOver { .. } if shutdown_deadline.is_some() => break,
+            GameState::WaitingForPlayers { .. } | GameState::GameOver { .. } => {},
+        }
+
+        scheduler.finish();
 
         tick_count += 1;
     }
 }
+
+/// Records that a direction change from `user_id` has been dealt with. Acks only ever go up, a
+/// queued turn can be made after a later one was already refused.
+fn ack_seq(
+    last_processed_seq: &mut HashMap<String, u64>,
+    user_id: &str,
+    seq: Option<u64>
+) {
+    if let Some(seq) = seq {
+        let acked = last_processed_seq.entry(user_id.to_string()).or_insert(seq);
+        *acked = (*acked).max(seq);
+    }
+}
+
+/// Grows every live snake whose head is on an apple and takes those apples off the board,
+/// returning who ate which apple by user id. When heads share an apple they all get it, so the
+/// outcome never depends on the order the snakes are stored in.
+fn eat_apples(
+    snakes: &mut HashMap<String, Snake>,
+    apples: &mut Vec<(u32, u32)>
+) -> Vec<(String, (u32, u32))> {
+    let mut eaten = vec!();
+    for (user_id, snake) in snakes.iter_mut() {
+        if let Snake::Alive(snake) = snake
+            && apples.contains(&snake.head) {
+            snake.grow(2);
+            eaten.push((user_id.to_string(), snake.head));
+        }
+    }
+    eaten.sort();
+    apples.retain(|apple| !eaten.iter().any(|(_, eaten)| eaten == apple));
+    eaten
+}
+
+/// Everyone still in the game, meaning alive or dead but still able to be revived
+fn standings(
+    snakes: &HashMap<String, Snake>,
+    stats: &MatchStats
+) -> Vec<Standing> {
+    snakes.iter().filter_map(|(user_id, snake)| {
+        let length = match snake {
+            Snake::Alive(snake) => snake.blocks.iter().map(|(_, len)| len).sum(),
+            Snake::Dead { ticks_to_revive: Some(_), .. } => 0,
+            Snake::Dead { ticks_to_revive: None, .. } => return None,
+        };
+        Some(Standing {
+            id: user_id.to_string(),
+            length,
+            apples_eaten: stats.apples_eaten(user_id),
+        })
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alive(user_id: &str, head: (u32, u32)) -> Snake {
+        Snake::Alive(AliveSnake::new(user_id, head, vec!((Direction::Up, START_LENGTH))))
+    }
+
+    fn length(snake: &Snake) -> u32 {
+        match snake {
+            Snake::Alive(snake) => snake.blocks.iter().map(|(_, len)| len).sum(),
+            Snake::Dead { .. } => 0,
+        }
+    }
+
+    #[test]
+    fn every_head_on_an_apple_eats_it() {
+        let mut snakes = HashMap::from([
+            ("b".to_string(), alive("b", (5, 5))),
+            ("a".to_string(), alive("a", (5, 5))),
+            ("c".to_string(), alive("c", (8, 8))),
+        ]);
+        let mut apples = vec!((5, 5), (1, 1));
+
+        let eaten = eat_apples(&mut snakes, &mut apples);
+
+        assert_eq!(eaten, vec!(("a".to_string(), (5, 5)), ("b".to_string(), (5, 5))));
+        assert_eq!(apples, vec!((1, 1)));
+        assert_eq!(length(&snakes["a"]), START_LENGTH + 2);
+        assert_eq!(length(&snakes["b"]), START_LENGTH + 2);
+        assert_eq!(length(&snakes["c"]), START_LENGTH);
+    }
+
+    #[test]
+    fn dead_snakes_do_not_eat() {
+        let mut snakes = HashMap::from([
+            ("a".to_string(), Snake::Dead { user_id: "a".to_string(), head: (5, 5), ticks_to_revive: Some(10) }),
+        ]);
+        let mut apples = vec!((5, 5));
+
+        assert!(eat_apples(&mut snakes, &mut apples).is_empty());
+        assert_eq!(apples, vec!((5, 5)));
+    }
+}
@@ -0,0 +1,40 @@
+use std::future::Future;
+use tokio::runtime::{Builder, Handle};
+
+/// Threads that run game simulations, away from the runtime handling sockets and the management
+/// api. Each worker is its own single threaded runtime, and a game always runs on the worker for
+/// its registry shard. Without any workers games run alongside everything else.
+#[derive(Debug, Default)]
+pub struct GameWorkers {
+    workers: Vec<Handle>,
+}
+
+impl GameWorkers {
+    /// Starts `threads` workers, each running until the server exits
+    pub fn start(threads: usize) -> Result<GameWorkers, String> {
+        let mut workers = vec!();
+        for index in 0..threads {
+            let runtime = Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(|err| format!("could not start game worker {index}: {err}"))?;
+            workers.push(runtime.handle().clone());
+            std::thread::Builder::new()
+                .name(format!("game-worker-{index}"))
+                .spawn(move || runtime.block_on(std::future::pending::<()>()))
+                .map_err(|err| format!("could not start game worker {index}: {err}"))?;
+        }
+        Ok(GameWorkers { workers })
+    }
+
+    /// Runs a game on the worker for `shard`
+    pub fn spawn<F>(&self, shard: usize, game: F)
+    where
+        F: Future<Output = ()> + Send + 'static
+    {
+        match self.workers.get(shard % self.workers.len().max(1)) {
+            Some(worker) => worker.spawn(game),
+            None => tokio::spawn(game),
+        };
+    }
+}
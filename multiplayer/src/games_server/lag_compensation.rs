@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::games_server::all_games_state::game_state::{Direction, Snake};
+use crate::games_server::board::Board;
+use crate::games_server::occupancy_grid::OccupancyGrid;
+
+/// However a game is set up, turns are never replayed further back than this many moves
+pub const MAX_REWIND_TICKS: u64 = 5;
+
+/// Turns that reach the server late are made from where the snake was when the player turned, so
+/// players on slow links aren't always turning a move or two after everyone else. A late turn is
+/// only replayed while nothing else has happened to the snake since, otherwise it is made on the
+/// next move like any other.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LagCompensationRules {
+    /// how many moves back a turn can be replayed, capped at [`MAX_REWIND_TICKS`]
+    #[serde(default = "default_max_ticks")]
+    pub max_ticks: u64,
+}
+
+fn default_max_ticks() -> u64 {
+    2
+}
+
+/// Where a snake's head was before one of its moves, kept so a late turn can be replayed
+#[derive(Debug, Clone)]
+pub struct RecentMove {
+    pub tick: u64,
+    pub head_before: (u32, u32),
+    /// the snake's length when it made the move
+    pub length: u32,
+}
+
+impl LagCompensationRules {
+    /// Turns `user_id`'s snake as if `direction` had arrived in time for the move after
+    /// `seen_tick`, the tick of the last state the player had, while `tick` is being played.
+    /// `seen_tick` comes from the client, so it is only believed as far back as the player's
+    /// measured round trip, `latency_ticks`, could have made it.
+    ///
+    /// Only happens if the snake has gone straight on every tick since without growing,
+    /// shrinking or being frozen, and every block it would have moved through instead is still
+    /// empty, going by `occupancy`. The block it ends up on is left to this tick's collisions
+    /// and apples like any other. Returns how many moves were replayed.
+    #[allow(clippy::too_many_arguments)]
+    pub fn compensate_turn(
+        &self,
+        user_id: &str,
+        direction: &Direction,
+        seen_tick: u64,
+        tick: u64,
+        latency_ticks: u64,
+        snakes: &mut HashMap<String, Snake>,
+        occupancy: &OccupancyGrid,
+        apples: &[(u32, u32)],
+        board: &Board
+    ) -> Option<u64> {
+        let turn_tick = seen_tick + 1;
+        let moves = tick.checked_sub(turn_tick)
+            .filter(|moves| *moves > 0 && *moves <= self.max_ticks.min(MAX_REWIND_TICKS).min(latency_ticks))?;
+
+        let Some(Snake::Alive(snake)) = snakes.get(user_id) else {
+            return None;
+        };
+        if snake.frozen_for.is_some()
+            || !snake.pending_directions.is_empty()
+            || *direction == snake.head_direction
+            || *direction == snake.head_direction.opposite()
+            || snake.blocks[0].0 != snake.head_direction
+            || snake.blocks[0].1 <= moves as u32 {
+            return None;
+        }
+
+        let length: u32 = snake.blocks.iter().map(|(_, len)| len).sum();
+        let window = snake.recent_moves.iter().skip(snake.recent_moves.len().checked_sub(moves as usize)?);
+        let mut start = None;
+        for (expected_tick, recent_move) in (turn_tick..tick).zip(window) {
+            if recent_move.tick != expected_tick || recent_move.length != length {
+                return None;
+            }
+            start.get_or_insert(recent_move.head_before);
+        }
+
+        let mut path = vec!();
+        let mut point = start?;
+        for _ in 0..moves {
+            point = board.step(point, direction);
+            path.push(point);
+        }
+
+        let passed_through = &path[..path.len() - 1];
+        if passed_through.iter().any(|point| board.is_wall(point) || apples.contains(point) || occupancy.is_occupied(point)) {
+            return None;
+        }
+
+        let Some(Snake::Alive(snake)) = snakes.get_mut(user_id) else {
+            return None;
+        };
+        snake.blocks[0].1 -= moves as u32;
+        snake.blocks.insert(0, (direction.clone(), moves as u32));
+        snake.head = point;
+        snake.head_direction = direction.clone();
+        // the moves it remembers no longer happened
+        snake.recent_moves.clear();
+        snake.generation += 1;
+        Some(moves)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::games_server::all_games_state::game_state::AliveSnake;
+
+    const RULES: LagCompensationRules = LagCompensationRules { max_ticks: 2 };
+
+    /// A snake heading right with its head on (10, 5), which moved on ticks 7 and 8 and is about
+    /// to move on tick 9
+    fn snakes(recent_moves: Vec<(u64, (u32, u32), u32)>) -> HashMap<String, Snake> {
+        let mut snake = AliveSnake::new("a", (10, 5), vec!((Direction::Right, 5)));
+        snake.recent_moves = recent_moves.into_iter()
+            .map(|(tick, head_before, length)| RecentMove { tick, head_before, length })
+            .collect();
+        HashMap::from([("a".to_string(), Snake::Alive(snake))])
+    }
+
+    fn went_straight() -> HashMap<String, Snake> {
+        snakes(vec!((6, (7, 5), 5), (7, (8, 5), 5), (8, (9, 5), 5)))
+    }
+
+    fn compensate(
+        snakes: &mut HashMap<String, Snake>,
+        seen_tick: u64,
+        latency_ticks: u64,
+        apples: &[(u32, u32)],
+        board: &Board
+    ) -> Option<u64> {
+        let mut occupancy = OccupancyGrid::new(board.size);
+        occupancy.sync(snakes, board);
+        RULES.compensate_turn("a", &Direction::Down, seen_tick, 9, latency_ticks, snakes, &occupancy, apples, board)
+    }
+
+    fn alive(snakes: &HashMap<String, Snake>) -> &AliveSnake {
+        match &snakes["a"] {
+            Snake::Alive(snake) => snake,
+            Snake::Dead { .. } => panic!("a is not alive"),
+        }
+    }
+
+    #[test]
+    fn replays_a_turn_from_where_the_snake_was() {
+        let mut snakes = went_straight();
+        assert_eq!(compensate(&mut snakes, 6, 5, &[], &Board::default()), Some(2));
+
+        let snake = alive(&snakes);
+        assert_eq!(snake.head, (8, 7));
+        assert_eq!(snake.head_direction, Direction::Down);
+        assert_eq!(snake.blocks, vec!((Direction::Down, 2), (Direction::Right, 3)));
+        assert!(snake.recent_moves.is_empty());
+        assert_eq!(snake.generation, 1);
+    }
+
+    #[test]
+    fn leaves_turns_that_are_not_late_to_the_next_move() {
+        let mut snakes = went_straight();
+        assert_eq!(compensate(&mut snakes, 8, 5, &[], &Board::default()), None);
+        assert_eq!(alive(&snakes).head, (10, 5));
+    }
+
+    #[test]
+    fn never_goes_back_further_than_the_round_trip_or_the_rules() {
+        let board = Board::default();
+        assert_eq!(compensate(&mut went_straight(), 6, 1, &[], &board), None);
+        assert_eq!(compensate(&mut went_straight(), 7, 1, &[], &board), Some(1));
+        // three moves back is past max_ticks
+        assert_eq!(compensate(&mut went_straight(), 5, 5, &[], &board), None);
+    }
+
+    #[test]
+    fn refuses_after_growing() {
+        let mut snakes = snakes(vec!((7, (8, 5), 4), (8, (9, 5), 4)));
+        assert_eq!(compensate(&mut snakes, 6, 5, &[], &Board::default()), None);
+        assert_eq!(alive(&snakes).head, (10, 5));
+    }
+
+    #[test]
+    fn refuses_across_a_tick_the_snake_was_frozen() {
+        // it didn't move on tick 7
+        let mut snakes = snakes(vec!((6, (8, 5), 5), (8, (9, 5), 5)));
+        assert_eq!(compensate(&mut snakes, 6, 5, &[], &Board::default()), None);
+    }
+
+    #[test]
+    fn refuses_to_pass_through_anything() {
+        let board = Board::default();
+        assert_eq!(compensate(&mut went_straight(), 6, 5, &[(8, 6)], &board), None);
+
+        let mut walled = board.clone();
+        walled.walls.insert((8, 6));
+        assert_eq!(compensate(&mut went_straight(), 6, 5, &[], &walled), None);
+
+        let mut snakes = went_straight();
+        snakes.insert("b".to_string(), Snake::Alive(AliveSnake::new("b", (9, 6), vec!((Direction::Right, 3)))));
+        assert_eq!(compensate(&mut snakes, 6, 5, &[], &board), None);
+
+        // the block it ends up on is left to the tick's collisions and apples
+        assert_eq!(compensate(&mut went_straight(), 6, 5, &[(8, 7)], &board), Some(2));
+    }
+}
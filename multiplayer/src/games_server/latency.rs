@@ -0,0 +1,86 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How often each connection is pinged
+pub const PING_EVERY: Duration = Duration::from_secs(2);
+/// pings older than this are forgotten, their pongs are ignored
+const MAX_OUTSTANDING_PINGS: usize = 4;
+/// how much each new measurement moves the estimates
+const SMOOTHING: f64 = 0.2;
+
+/// Round trip time and clock offset to one player, smoothed over every ping they answer
+#[derive(Debug, Default)]
+pub struct LatencyTracker {
+    next_id: u64,
+    /// pings still waiting on a pong, with when they were sent and the server's unix time in
+    /// milliseconds at that moment
+    outstanding: VecDeque<(u64, Instant, u64)>,
+    rtt_ms: Option<f64>,
+    clock_offset_ms: Option<f64>,
+}
+
+/// What a pong told us about the connection
+#[derive(Debug, Clone, Copy)]
+pub struct LatencySample {
+    /// round trip time of this ping
+    pub rtt: Duration,
+    /// smoothed round trip time
+    pub rtt_ms: u64,
+    /// smoothed estimate of how far the client's clock is ahead of the server's
+    pub clock_offset_ms: i64,
+}
+
+impl LatencyTracker {
+    /// Smoothed round trip time, `None` until a ping has been answered
+    pub fn rtt_ms(&self) -> Option<u64> {
+        self.rtt_ms.map(|rtt_ms| rtt_ms.round() as u64)
+    }
+
+    /// Remembers a ping being sent, returning its id
+    pub fn ping(&mut self, server_time_ms: u64) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        if self.outstanding.len() == MAX_OUTSTANDING_PINGS {
+            self.outstanding.pop_front();
+        }
+        self.outstanding.push_back((id, Instant::now(), server_time_ms));
+        id
+    }
+
+    /// Takes the answer to ping `id`, `client_time_ms` being the client's unix time in
+    /// milliseconds when it answered. Returns `None` for pongs to pings we never sent or have
+    /// forgotten.
+    pub fn pong(&mut self, id: u64, client_time_ms: u64) -> Option<LatencySample> {
+        let index = self.outstanding.iter().position(|(ping_id, _, _)| *ping_id == id)?;
+        let (_, sent_at, server_time_ms) = self.outstanding.remove(index)?;
+        // anything sent before this ping can't be answered anymore
+        self.outstanding.drain(..index);
+
+        let rtt = sent_at.elapsed();
+        let rtt_ms = rtt.as_secs_f64() * 1000.0;
+        // the client answered about half way through the round trip
+        let clock_offset_ms = client_time_ms as f64 - (server_time_ms as f64 + rtt_ms / 2.0);
+
+        let rtt_ms = smooth(self.rtt_ms, rtt_ms);
+        let clock_offset_ms = smooth(self.clock_offset_ms, clock_offset_ms);
+        self.rtt_ms = Some(rtt_ms);
+        self.clock_offset_ms = Some(clock_offset_ms);
+
+        Some(LatencySample {
+            rtt,
+            rtt_ms: rtt_ms.round() as u64,
+            clock_offset_ms: clock_offset_ms.round() as i64,
+        })
+    }
+}
+
+fn smooth(
+    estimate: Option<f64>,
+    sample: f64
+) -> f64 {
+    match estimate {
+        Some(estimate) => estimate + SMOOTHING * (sample - estimate),
+        None => sample,
+    }
+}
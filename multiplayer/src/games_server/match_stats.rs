@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::games_server::all_games_state::game_state::Snake;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PlayerStats {
+    pub user_id: String,
+    pub apples_eaten: u32,
+    pub kills: u32,
+    pub deaths: u32,
+    /// longest the snake has been at any point in the game
+    pub max_length: u32,
+    pub time_alive_ms: u64,
+}
+
+/// Running totals for every player in a game, updated by the game runner as things happen
+#[derive(Debug, Clone, Default)]
+pub struct MatchStats {
+    players: HashMap<String, PlayerStats>,
+}
+
+impl MatchStats {
+    pub fn new(user_ids: &[String]) -> MatchStats {
+        MatchStats {
+            players: user_ids.iter().map(|user_id| (user_id.to_string(), PlayerStats {
+                user_id: user_id.to_string(),
+                ..PlayerStats::default()
+            })).collect(),
+        }
+    }
+
+    fn player(&mut self, user_id: &str) -> &mut PlayerStats {
+        self.players.entry(user_id.to_string()).or_insert_with(|| PlayerStats {
+            user_id: user_id.to_string(),
+            ..PlayerStats::default()
+        })
+    }
+
+    pub fn ate_apple(&mut self, user_id: &str) {
+        self.player(user_id).apples_eaten += 1;
+    }
+
+    pub fn died(&mut self, user_id: &str, killer: Option<&String>) {
+        self.player(user_id).deaths += 1;
+        if let Some(killer) = killer {
+            self.player(killer).kills += 1;
+        }
+    }
+
+    /// Counts another tick of time alive for every living snake and keeps track of how long each
+    /// has grown
+    pub fn tick(&mut self, snakes: &HashMap<String, Snake>, tick_time_ms: u64) {
+        for (user_id, snake) in snakes {
+            if let Snake::Alive(snake) = snake {
+                let length = snake.blocks.iter().map(|(_, len)| len).sum();
+                let stats = self.player(user_id);
+                stats.time_alive_ms += tick_time_ms;
+                stats.max_length = stats.max_length.max(length);
+            }
+        }
+    }
+
+    pub fn apples_eaten(&self, user_id: &str) -> u32 {
+        self.players.get(user_id).map(|stats| stats.apples_eaten).unwrap_or(0)
+    }
+
+    /// Every player's stats, sorted by user id
+    pub fn all(&self) -> Vec<PlayerStats> {
+        let mut all: Vec<_> = self.players.values().cloned().collect();
+        all.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+        all
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use crate::games_server::all_games_state::game_state::{AliveSnake, Snake};
+use crate::games_server::board::Board;
+use crate::games_server::overlap_detector::snake_cells;
+
+/// Which snakes are on every block of the board, kept up to date as snakes move so nothing has
+/// to walk every snake's body to find out what is on a block
+#[derive(Debug)]
+pub struct OccupancyGrid {
+    width: u32,
+    /// every snake on each block, once for each of its blocks there, indexed by `y * width + x`
+    cells: Vec<Vec<Arc<str>>>,
+    /// the blocks of every living snake, head first
+    bodies: HashMap<Arc<str>, VecDeque<(u32, u32)>>,
+    /// the `generation` of every living snake when its body was last walked
+    generations: HashMap<Arc<str>, u64>,
+}
+
+impl OccupancyGrid {
+    pub fn new(board_size: (u32, u32)) -> OccupancyGrid {
+        OccupancyGrid {
+            width: board_size.0,
+            cells: vec![vec!(); (board_size.0 * board_size.1) as usize],
+            bodies: HashMap::new(),
+            generations: HashMap::new(),
+        }
+    }
+
+    /// Catches up with everything that happened to the snakes other than moving, like eating,
+    /// shrinking, dying or being revived. Only snakes whose `generation` went up, or that are new
+    /// since the last sync, are walked.
+    pub fn sync(
+        &mut self,
+        snakes: &HashMap<String, Snake>,
+        board: &Board
+    ) {
+        let gone: Vec<Arc<str>> = self.bodies.keys()
+            .filter(|user_id| !matches!(snakes.get(&***user_id), Some(Snake::Alive(_))))
+            .cloned()
+            .collect();
+        for user_id in gone {
+            self.remove(&user_id);
+        }
+
+        for snake in snakes.values() {
+            if let Snake::Alive(snake) = snake && !self.is_current(snake) {
+                self.rebuild(snake, board);
+            }
+        }
+    }
+
+    /// Follows a snake that just moved one block forward
+    pub fn moved(
+        &mut self,
+        snake: &AliveSnake,
+        board: &Board
+    ) {
+        let length = snake_length(snake);
+        let Some(body) = self.bodies.get_mut(snake.user_id.as_str()) else {
+            self.rebuild(snake, board);
+            return;
+        };
+        body.push_front(snake.head);
+        let mut left_behind = vec!();
+        while body.len() > length {
+            left_behind.extend(body.pop_back());
+        }
+        let user_id = self.key(&snake.user_id);
+        self.add_cell(&user_id, snake.head);
+        for point in left_behind {
+            self.remove_cell(&user_id, point);
+        }
+
+        // a snake that grew or shrank since the last sync has a tail we don't know about
+        if !self.is_current(snake) {
+            self.rebuild(snake, board);
+        }
+    }
+
+    /// Every snake with a block on `point`, once for each of its blocks there
+    pub fn occupants(&self, point: &(u32, u32)) -> &[Arc<str>] {
+        &self.cells[self.index(point)]
+    }
+
+    pub fn is_occupied(&self, point: &(u32, u32)) -> bool {
+        !self.occupants(point).is_empty()
+    }
+
+    /// How many of `user_id`'s blocks are on `point`
+    pub fn count(&self, user_id: &str, point: &(u32, u32)) -> usize {
+        self.occupants(point).iter().filter(|occupant| ***occupant == *user_id).count()
+    }
+
+    /// The blocks of a living snake, head first
+    pub fn body(&self, user_id: &str) -> Option<&VecDeque<(u32, u32)>> {
+        self.bodies.get(user_id)
+    }
+
+    /// Every block with a snake on it, blocks with more than one are repeated
+    pub fn occupied(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        self.bodies.values().flatten().copied()
+    }
+
+    fn is_current(&self, snake: &AliveSnake) -> bool {
+        self.generations.get(snake.user_id.as_str()) == Some(&snake.generation)
+            && self.bodies.get(snake.user_id.as_str())
+                .is_some_and(|body| body.front() == Some(&snake.head) && body.len() == snake_length(snake))
+    }
+
+    fn rebuild(
+        &mut self,
+        snake: &AliveSnake,
+        board: &Board
+    ) {
+        self.remove(&snake.user_id);
+        let user_id: Arc<str> = Arc::from(snake.user_id.as_str());
+        let body: VecDeque<(u32, u32)> = snake_cells(snake, board).into();
+        for point in &body {
+            self.add_cell(&user_id, *point);
+        }
+        self.generations.insert(Arc::clone(&user_id), snake.generation);
+        self.bodies.insert(user_id, body);
+    }
+
+    fn remove(&mut self, user_id: &str) {
+        self.generations.remove(user_id);
+        if let Some((user_id, body)) = self.bodies.remove_entry(user_id) {
+            for point in body {
+                self.remove_cell(&user_id, point);
+            }
+        }
+    }
+
+    /// The shared copy of `user_id`, so every block doesn't need its own
+    fn key(&self, user_id: &str) -> Arc<str> {
+        match self.bodies.get_key_value(user_id) {
+            Some((key, _)) => Arc::clone(key),
+            None => Arc::from(user_id),
+        }
+    }
+
+    fn add_cell(&mut self, user_id: &Arc<str>, point: (u32, u32)) {
+        let index = self.index(&point);
+        self.cells[index].push(Arc::clone(user_id));
+    }
+
+    fn remove_cell(&mut self, user_id: &str, point: (u32, u32)) {
+        let index = self.index(&point);
+        let cell = &mut self.cells[index];
+        if let Some(position) = cell.iter().position(|occupant| **occupant == *user_id) {
+            cell.swap_remove(position);
+        }
+    }
+
+    fn index(&self, (x, y): &(u32, u32)) -> usize {
+        (y * self.width + x) as usize
+    }
+}
+
+fn snake_length(snake: &AliveSnake) -> usize {
+    snake.blocks.iter().map(|(_, len)| *len as usize).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::games_server::all_games_state::game_state::Direction;
+
+    fn alive(user_id: &str, head: (u32, u32), blocks: Vec<(Direction, u32)>) -> AliveSnake {
+        AliveSnake::new(user_id, head, blocks)
+    }
+
+    /// Moves the snake one block forward the way the game runner does
+    fn move_forward(snake: &mut AliveSnake, board: &Board) {
+        snake.head = board.step(snake.head, &snake.head_direction);
+        if snake.head_direction != snake.blocks[0].0 {
+            snake.blocks.insert(0, (snake.head_direction.clone(), 0));
+        }
+        snake.blocks[0].1 += 1;
+        let blocks_len = snake.blocks.len();
+        snake.blocks[blocks_len - 1].1 -= 1;
+        if snake.blocks[blocks_len - 1].1 == 0 {
+            snake.blocks.pop();
+        }
+    }
+
+    fn assert_matches(occupancy: &OccupancyGrid, snakes: &HashMap<String, Snake>, board: &Board) {
+        let mut expected: HashMap<(u32, u32), Vec<String>> = HashMap::new();
+        for snake in snakes.values() {
+            let Snake::Alive(snake) = snake else {
+                assert!(occupancy.body(snake_id(snake)).is_none());
+                continue;
+            };
+            let cells = snake_cells(snake, board);
+            assert_eq!(occupancy.body(&snake.user_id), Some(&VecDeque::from(cells.clone())));
+            for point in cells {
+                expected.entry(point).or_default().push(snake.user_id.to_string());
+            }
+        }
+        for x in 0..board.size.0 {
+            for y in 0..board.size.1 {
+                let mut occupants: Vec<String> = occupancy.occupants(&(x, y)).iter().map(|user_id| user_id.to_string()).collect();
+                occupants.sort();
+                let mut on_point = expected.remove(&(x, y)).unwrap_or_default();
+                on_point.sort();
+                assert_eq!(occupants, on_point, "occupants of {:?}", (x, y));
+            }
+        }
+    }
+
+    fn snake_id(snake: &Snake) -> &str {
+        match snake {
+            Snake::Alive(snake) => &snake.user_id,
+            Snake::Dead { user_id, .. } => user_id,
+        }
+    }
+
+    fn alive_mut<'a>(snakes: &'a mut HashMap<String, Snake>, user_id: &str) -> &'a mut AliveSnake {
+        match snakes.get_mut(user_id) {
+            Some(Snake::Alive(snake)) => snake,
+            _ => panic!("{user_id} is not alive"),
+        }
+    }
+
+    /// Moves every snake and lets the grid follow, like a tick does
+    fn move_all(snakes: &mut HashMap<String, Snake>, occupancy: &mut OccupancyGrid, board: &Board) {
+        for snake in snakes.values_mut() {
+            if let Snake::Alive(snake) = snake {
+                move_forward(snake, board);
+                occupancy.moved(snake, board);
+            }
+        }
+    }
+
+    fn setup(board: &Board, snakes: Vec<AliveSnake>) -> (HashMap<String, Snake>, OccupancyGrid) {
+        let snakes: HashMap<String, Snake> = snakes.into_iter().map(|snake| (snake.user_id.to_string(), Snake::Alive(snake))).collect();
+        let mut occupancy = OccupancyGrid::new(board.size);
+        occupancy.sync(&snakes, board);
+        (snakes, occupancy)
+    }
+
+    #[test]
+    fn follows_moves_and_turns() {
+        let board = Board::default();
+        let (mut snakes, mut occupancy) = setup(&board, vec!(
+            alive("a", (5, 5), vec!((Direction::Right, 4))),
+            alive("b", (0, 0), vec!((Direction::Left, 3))),
+        ));
+        for turn in [Direction::Right, Direction::Down, Direction::Down, Direction::Left, Direction::Up] {
+            alive_mut(&mut snakes, "a").head_direction = turn;
+            move_all(&mut snakes, &mut occupancy, &board);
+            assert_matches(&occupancy, &snakes, &board);
+        }
+    }
+
+    #[test]
+    fn follows_growing() {
+        let board = Board::default();
+        let (mut snakes, mut occupancy) = setup(&board, vec!(alive("a", (5, 5), vec!((Direction::Right, 3)))));
+        alive_mut(&mut snakes, "a").grow(2);
+        occupancy.sync(&snakes, &board);
+        assert_matches(&occupancy, &snakes, &board);
+
+        // growing right before a move is picked up by the move
+        alive_mut(&mut snakes, "a").grow(2);
+        for _ in 0..3 {
+            move_all(&mut snakes, &mut occupancy, &board);
+            assert_matches(&occupancy, &snakes, &board);
+        }
+    }
+
+    #[test]
+    fn follows_shrinking() {
+        let board = Board::default();
+        let (mut snakes, mut occupancy) = setup(&board, vec!(alive("a", (10, 5), vec!((Direction::Right, 5), (Direction::Down, 8)))));
+        alive_mut(&mut snakes, "a").shrink(6);
+        occupancy.sync(&snakes, &board);
+        assert_matches(&occupancy, &snakes, &board);
+    }
+
+    #[test]
+    fn shrinking_and_growing_by_the_same_amount_is_noticed() {
+        let board = Board::default();
+        let (mut snakes, mut occupancy) = setup(&board, vec!(alive("a", (10, 5), vec!((Direction::Right, 5), (Direction::Down, 10)))));
+        let snake = alive_mut(&mut snakes, "a");
+        snake.shrink(10);
+        snake.grow(10);
+        occupancy.sync(&snakes, &board);
+        assert_matches(&occupancy, &snakes, &board);
+        assert!(occupancy.occupants(&(5, 0)).is_empty());
+    }
+
+    #[test]
+    fn follows_teleports() {
+        let board = Board::parse(r#"
+            layout = """
+            ..........
+            .A......B.
+            ..........
+            .B......A.
+            """
+        "#).unwrap();
+        let (mut snakes, mut occupancy) = setup(&board, vec!(alive("a", (4, 1), vec!((Direction::Left, 3)))));
+        for _ in 0..6 {
+            move_all(&mut snakes, &mut occupancy, &board);
+            assert_matches(&occupancy, &snakes, &board);
+        }
+        alive_mut(&mut snakes, "a").grow(3);
+        occupancy.sync(&snakes, &board);
+        assert_matches(&occupancy, &snakes, &board);
+    }
+
+    #[test]
+    fn forgets_dead_snakes() {
+        let board = Board::default();
+        let (mut snakes, mut occupancy) = setup(&board, vec!(alive("a", (5, 5), vec!((Direction::Right, 3)))));
+        snakes.insert("a".to_string(), Snake::Dead { user_id: "a".to_string(), head: (5, 5), ticks_to_revive: Some(10) });
+        occupancy.sync(&snakes, &board);
+        assert_matches(&occupancy, &snakes, &board);
+    }
+}
@@ -1,24 +1,19 @@
-use crate::games_server::all_games_state::game_state::{AliveSnake, Direction};
+use crate::games_server::all_games_state::game_state::AliveSnake;
+use crate::games_server::board::Board;
 
-pub fn detect_overlap(
-    point: &(u32, u32),
-    snake: &AliveSnake
-) -> bool {
-    let mut head = snake.head;
+/// Every block the snake occupies, starting at the head, following it around the board edges and
+/// back through teleports
+pub fn snake_cells(
+    snake: &AliveSnake,
+    board: &Board
+) -> Vec<(u32, u32)> {
+    let mut cells = vec!();
+    let mut point = snake.head;
     for (dir, block_len) in &snake.blocks {
         for _ in 0..*block_len {
-            if head == *point {
-                return true;
-            }
-
-            let delta_head: (i32, i32) = match dir {
-                Direction::Up => (0, 1),
-                Direction::Down => (0, -1),
-                Direction::Left => (1, 0),
-                Direction::Right => (-1, 0),
-            };
-            head = ((head.0 as i32 + delta_head.0) as u32, (head.1 as i32 + delta_head.1) as u32);
+            cells.push(point);
+            point = board.step_back(point, dir);
         }
     }
-    false
+    cells
 }
@@ -11,6 +11,19 @@ pub enum PowerUps {
     Revive
 }
 
+impl PowerUps {
+    /// The name of the kind of power up, without who it targets
+    pub fn name(&self) -> &'static str {
+        match self {
+            PowerUps::ExtraLife => "ExtraLife",
+            PowerUps::AddLength => "AddLength",
+            PowerUps::ShrinkOpponent { .. } => "ShrinkOpponent",
+            PowerUps::FreezeOpponent { .. } => "FreezeOpponent",
+            PowerUps::Revive => "Revive",
+        }
+    }
+}
+
 pub trait GetPowerUpCost {
     fn get_cost(&self, power_up: &PowerUps) -> f64;
 }
\ No newline at end of file
@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+use serde::Serialize;
+
+/// How one player's connection to a game is doing
+#[derive(Debug, Serialize, Clone)]
+pub struct PlayerPresence {
+    pub user_id: String,
+    pub connected: bool,
+    /// smoothed round trip time, `None` until they have answered a ping
+    pub rtt_ms: Option<u64>,
+}
+
+/// Who is connected to a game and how good their links are, kept up to date by the game's
+/// connections and sent to every player by its runner
+#[derive(Debug, Default)]
+pub struct Presence {
+    /// user id to open connections and latest round trip time
+    players: HashMap<String, (u32, Option<u64>)>,
+}
+
+impl Presence {
+    pub fn connected(&mut self, user_id: &str) {
+        self.players.entry(user_id.to_string()).or_default().0 += 1;
+    }
+
+    pub fn disconnected(&mut self, user_id: &str) {
+        if let Some((connections, _)) = self.players.get_mut(user_id) {
+            *connections = connections.saturating_sub(1);
+        }
+    }
+
+    pub fn set_rtt(&mut self, user_id: &str, rtt_ms: u64) {
+        self.players.entry(user_id.to_string()).or_default().1 = Some(rtt_ms);
+    }
+
+    /// How every player in the game is doing, including those who never connected
+    pub fn players(&self, user_ids: &[String]) -> Vec<PlayerPresence> {
+        user_ids.iter().map(|user_id| {
+            let (connections, rtt_ms) = self.players.get(user_id).copied().unwrap_or_default();
+            PlayerPresence {
+                user_id: user_id.to_string(),
+                connected: connections > 0,
+                rtt_ms,
+            }
+        }).collect()
+    }
+}
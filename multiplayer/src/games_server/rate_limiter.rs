@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use tokio::time::Instant;
+use crate::games_server::client_message::ClientMessage;
+
+/// Rejected messages a connection can send in a burst before it is disconnected
+const MAX_REJECTED_MESSAGES: f64 = 50.0;
+/// how many rejected messages a second are forgiven, so a long game of occasional bursts is fine
+const FORGIVEN_PER_SEC: f64 = 5.0;
+
+/// Lets through bursts of up to `capacity` messages, refilling at `per_sec` a second
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    per_sec: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: f64, per_sec: f64) -> TokenBucket {
+        TokenBucket { capacity, per_sec, tokens: capacity, last_refill: Instant::now() }
+    }
+
+    fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_sec).min(self.capacity);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// How often one connection may send each kind of message. A player turns a few times a second
+/// at most, anything far beyond that is a script.
+#[derive(Debug)]
+pub struct RateLimiter {
+    buckets: HashMap<&'static str, TokenBucket>,
+    /// every rejected message takes a token, running out means the connection is abusive
+    rejections: TokenBucket,
+    abusive: bool,
+}
+
+impl Default for RateLimiter {
+    fn default() -> RateLimiter {
+        RateLimiter {
+            buckets: HashMap::from([
+                ("SetDirection", TokenBucket::new(20.0, 20.0)),
+                ("UsePowerUp", TokenBucket::new(5.0, 2.0)),
+                ("SetReady", TokenBucket::new(5.0, 1.0)),
+                ("Authenticate", TokenBucket::new(2.0, 0.2)),
+                ("Pong", TokenBucket::new(3.0, 1.0)),
+            ]),
+            rejections: TokenBucket::new(MAX_REJECTED_MESSAGES, FORGIVEN_PER_SEC),
+            abusive: false,
+        }
+    }
+}
+
+impl RateLimiter {
+    /// Whether the message may be passed on to the game
+    pub fn allow(&mut self, message: &ClientMessage) -> bool {
+        let now = Instant::now();
+        let allowed = self.buckets.get_mut(message.name())
+            .is_none_or(|bucket| bucket.try_take(now));
+        if !allowed && !self.rejections.try_take(now) {
+            self.abusive = true;
+        }
+        allowed
+    }
+
+    /// Counts a message that was turned away before it could be rate limited, like one that
+    /// couldn't be read
+    pub fn reject(&mut self) {
+        if !self.rejections.try_take(Instant::now()) {
+            self.abusive = true;
+        }
+    }
+
+    /// Whether the connection has been turned away so often it should be disconnected
+    pub fn is_abusive(&self) -> bool {
+        self.abusive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    fn authenticate() -> ClientMessage {
+        ClientMessage::Authenticate { access_token: "token".to_string(), game_id: "game".to_string() }
+    }
+
+    #[test]
+    fn a_bucket_runs_out_after_its_burst() {
+        let mut bucket = TokenBucket::new(3.0, 1.0);
+        let now = bucket.last_refill;
+        assert!((0..3).all(|_| bucket.try_take(now)));
+        assert!(!bucket.try_take(now));
+    }
+
+    #[test]
+    fn a_bucket_refills_over_time_up_to_its_capacity() {
+        let mut bucket = TokenBucket::new(3.0, 2.0);
+        let start = bucket.last_refill;
+        assert!((0..3).all(|_| bucket.try_take(start)));
+
+        assert!(!bucket.try_take(start + Duration::from_millis(400)));
+        assert!(bucket.try_take(start + Duration::from_millis(500)));
+        assert!(!bucket.try_take(start + Duration::from_millis(500)));
+
+        let later = start + Duration::from_secs(60);
+        assert!((0..3).all(|_| bucket.try_take(later)));
+        assert!(!bucket.try_take(later));
+    }
+
+    #[test]
+    fn limits_each_kind_of_message_on_its_own() {
+        let mut limiter = RateLimiter::default();
+        assert!(limiter.allow(&authenticate()));
+        assert!(limiter.allow(&authenticate()));
+        assert!(!limiter.allow(&authenticate()));
+        assert!(limiter.allow(&ClientMessage::SetReady { ready: true }));
+        assert!(!limiter.is_abusive());
+    }
+
+    #[test]
+    fn too_many_rejections_is_abusive() {
+        let mut limiter = RateLimiter::default();
+        for _ in 0..2 {
+            limiter.allow(&authenticate());
+        }
+        for _ in 0..MAX_REJECTED_MESSAGES as usize {
+            assert!(!limiter.allow(&authenticate()));
+        }
+        assert!(!limiter.is_abusive());
+
+        limiter.reject();
+        assert!(limiter.is_abusive());
+    }
+}
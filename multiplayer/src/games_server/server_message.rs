@@ -1,6 +1,12 @@
-use serde::Serialize;
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
 use crate::games_server::all_games_state::game_state::{Direction, Snake};
+use crate::games_server::board::Board;
+use crate::games_server::errors::{ConnectionError, GameError};
+use crate::games_server::match_stats::PlayerStats;
 use crate::games_server::power_ups::PowerUps;
+use crate::games_server::presence::PlayerPresence;
+use crate::games_server::win_conditions::MatchResult;
 
 #[derive(Debug, Serialize)]
 #[serde(tag = "type")]
@@ -10,9 +16,9 @@ pub enum SentSnake {
     Dead { user_id: String, revive_left: u64 },
 }
 
-impl Into<SentSnake> for &Snake {
-    fn into(self) -> SentSnake {
-        match self {
+impl From<&Snake> for SentSnake {
+    fn from(snake: &Snake) -> SentSnake {
+        match snake {
             Snake::Alive(snake) => SentSnake::Alive {
                 invulnerable: snake.invulnerable_for.is_some(),
                 frozen: snake.frozen_for.is_some(),
@@ -40,13 +46,27 @@ pub struct ReadyStatus {
     pub ready: bool
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 #[derive(Clone)]
 pub struct AmountSpent {
     pub user_id: String,
     pub amount_spent: f64
 }
 
+#[derive(Debug, Serialize)]
+#[derive(Clone)]
+pub struct TeamAmountSpent {
+    pub team: String,
+    pub amount_spent: f64
+}
+
+#[derive(Debug, Serialize)]
+#[derive(Clone)]
+pub struct Team {
+    pub team: String,
+    pub user_ids: Vec<String>
+}
+
 #[derive(Debug, Serialize)]
 #[derive(Clone)]
 pub struct RecentPowerUp {
@@ -54,13 +74,89 @@ pub struct RecentPowerUp {
     pub power_up: PowerUps
 }
 
+#[derive(Debug, Serialize)]
+#[derive(Clone)]
+pub struct SentBoard {
+    pub size: (u32, u32),
+    pub walls: Vec<(u32, u32)>,
+    /// each teleport appears once, as its two ends
+    pub teleports: Vec<((u32, u32), (u32, u32))>
+}
+
+impl From<&Board> for SentBoard {
+    fn from(board: &Board) -> SentBoard {
+        let mut walls: Vec<_> = board.walls.iter().copied().collect();
+        walls.sort();
+        let mut teleports: Vec<_> = board.teleports.iter()
+            .filter(|(from, to)| from < to)
+            .map(|(from, to)| (*from, *to))
+            .collect();
+        teleports.sort();
+        SentBoard {
+            size: board.size,
+            walls,
+            teleports,
+        }
+    }
+}
+
+/// Something that happened during a tick, for kill feeds and sound effects
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "type")]
+pub enum GameEvent {
+    /// `killed_by` is `None` when the snake ran into a wall or itself
+    Died { user_id: String, killed_by: Option<String> },
+    /// the snake would have died but used up its extra life instead
+    ExtraLifeUsed { user_id: String, killed_by: Option<String> },
+    AteApple { user_id: String, apple: (u32, u32) },
+    /// `target` is the opponent the power up was used on, if it was used on one
+    PowerUpUsed { user_id: String, power_up: PowerUps, target: Option<String> },
+    Revived { user_id: String, head: (u32, u32) },
+    /// a turn that arrived late was made `moves` moves back, where the player turned
+    TurnCompensated { user_id: String, moves: u64 },
+}
+
 #[derive(Debug, Serialize, Clone)]
 #[serde(tag = "type")]
 pub enum ServerMessage {
     Authenticated,
     /// vector of 
     ReadyStatus{status:Vec<ReadyStatus>},
-    StartGame,
-    GameOver {winner: String, amounts_spent: Vec<AmountSpent>},
-    GameState { apples: Vec<(u32, u32)>, snakes: Vec<SentSnake>, just_ate_apple: Vec<String>, recent_power_ups: Vec<RecentPowerUp> }
+    /// `teams` is empty unless this is a team game
+    StartGame {board: SentBoard, teams: Vec<Team>},
+    /// revives and extra lives can no longer be bought and the arena will start shrinking
+    SuddenDeath,
+    /// blocks that just turned into walls
+    ArenaShrunk {walls: Vec<(u32, u32)>},
+    /// the server is restarting, games still going after `deadline_secs` are stopped and
+    /// everything spent in them refunded
+    ServerShuttingDown {deadline_secs: u64},
+    /// `winner` is `None` when the game is a draw or was won by a team
+    GameOver {winner: Option<String>, result: MatchResult, amounts_spent: Vec<AmountSpent>, team_amounts_spent: Vec<TeamAmountSpent>, stats: Vec<PlayerStats>},
+    /// everything that happened this tick, only sent when something did
+    Events {events: Vec<GameEvent>},
+    /// only sent to the player whose request was turned down
+    GameError {error: GameError},
+    /// only sent to the player on that connection, sent before closing it if it can't continue
+    ConnectionError {error: ConnectionError},
+    /// only sent to the player on that connection, who should answer with a `Pong` straight away
+    Ping {id: u64, server_time_ms: u64},
+    /// only sent to the player on that connection after each `Pong`. `clock_offset_ms` is how far
+    /// the client's clock is ahead of the server's, so server time is about client time minus it.
+    Latency {rtt_ms: u64, clock_offset_ms: i64},
+    /// how everyone's connection to the game is doing, sent every second
+    Presence {players: Vec<PlayerPresence>},
+    /// `tick` is the tick this is the state after and `server_time_ms` is unix time in milliseconds
+    /// when it was sent. `last_processed_seq` maps each user id to the highest `seq` from them
+    /// that has been applied to their snake or refused, it never goes down.
+    GameState {
+        tick: u64,
+        server_time_ms: u64,
+        last_processed_seq: HashMap<String, u64>,
+        apples: Vec<(u32, u32)>,
+        snakes: Vec<SentSnake>,
+        just_ate_apple: Vec<String>,
+        recent_power_ups: Vec<RecentPowerUp>,
+        stats: Vec<PlayerStats>
+    }
 }
@@ -0,0 +1,85 @@
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+/// Keeps track of the server draining before it shuts down. Once draining no new games are
+/// created, lobbies close and games in progress get until the deadline to finish.
+#[derive(Debug)]
+pub struct Shutdown {
+    /// when games still going get cut off, `None` until the server starts draining
+    deadline: watch::Sender<Option<Instant>>,
+    /// how many games have started and not finished yet
+    games_in_progress: watch::Sender<usize>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Shutdown {
+        Shutdown {
+            deadline: watch::Sender::new(None),
+            games_in_progress: watch::Sender::new(0),
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn is_draining(&self) -> bool {
+        self.deadline.borrow().is_some()
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        *self.deadline.borrow()
+    }
+
+    pub fn game_started(&self) {
+        self.games_in_progress.send_modify(|games| *games += 1);
+    }
+
+    /// Called when a game ends, or is saved to be settled after the restart
+    pub fn game_finished(&self) {
+        self.games_in_progress.send_modify(|games| *games = games.saturating_sub(1));
+    }
+
+    /// Starts draining and waits for every game in progress to finish, or to be cut off
+    /// at the deadline
+    pub async fn drain(&self, grace_period: Duration) {
+        let deadline = Instant::now() + grace_period;
+        self.deadline.send_replace(Some(deadline));
+        info!(games_in_progress = *self.games_in_progress.borrow(), ?grace_period, "Draining");
+
+        let mut games_in_progress = self.games_in_progress.subscribe();
+        // leave the games a moment past the deadline to save themselves
+        let finished = tokio::time::timeout_at(
+            deadline + Duration::from_secs(1),
+            games_in_progress.wait_for(|games| *games == 0)
+        ).await;
+        if finished.is_err() {
+            warn!(games_in_progress = *self.games_in_progress.borrow(), "Games did not stop by the deadline");
+        }
+    }
+}
+
+/// Resolves once the process is asked to stop, by SIGTERM or ctrl-c
+pub async fn shutdown_signal() {
+    let ctrl_c = tokio::signal::ctrl_c();
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                error!(%err, "Could not listen for SIGTERM");
+                std::future::pending::<()>().await
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
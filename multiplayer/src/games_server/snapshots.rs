@@ -0,0 +1,249 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+use crate::fs_util::replace_file;
+use crate::games_server::all_games_state::AllGamesState;
+use crate::games_server::board::Board;
+use crate::games_server::game_rules::GameRules;
+use crate::games_server::game_runner::{GameConfig, TICK_TIME_MS};
+use crate::games_server::match_stats::PlayerStats;
+use crate::games_server::power_up_cost_loader::PowerUpCosts;
+use crate::games_server::server_message::AmountSpent;
+use crate::games_server::teams::Teams;
+use crate::games_server::win_conditions::MatchResult;
+use crate::management_server::create_game::start_game;
+use crate::management_server::match_history::{MatchHistory, MatchRecord};
+use crate::management_server::unix_time_secs;
+
+const SNAPSHOTS_DIR: &str = "games";
+
+/// Enough about a game in progress to bring it back after the server restarts
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GameSnapshot {
+    pub game_id: String,
+    pub fund_id: Option<String>,
+    pub user_ids: Vec<String>,
+    /// map of access tokens to player ids, so players can rejoin with the tokens they were given
+    pub access_tokens: HashMap<String, String>,
+    pub rules: GameRules,
+    pub teams: BTreeMap<String, Vec<String>>,
+    pub phase: SnapshotPhase,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum SnapshotPhase {
+    /// still waiting for players, the lobby is reopened with nobody ready
+    Lobby,
+    /// part way through, the game can't be picked back up so everyone is refunded
+    Playing {
+        tick: u64,
+        /// unix time in seconds
+        started_at: u64,
+        amounts_spent: Vec<AmountSpent>,
+        stats: Vec<PlayerStats>,
+    },
+}
+
+fn snapshots_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join(SNAPSHOTS_DIR)
+}
+
+fn snapshot_path(data_dir: &Path, game_id: &str) -> PathBuf {
+    snapshots_dir(data_dir).join(format!("{game_id}.json"))
+}
+
+impl GameSnapshot {
+    /// Writes the snapshot over the game's previous one, only replacing it once the new one is complete
+    pub fn save(&self, data_dir: &Path) -> Result<(), String> {
+        let json = serde_json::to_vec(self).map_err(|err| format!("could not serialize snapshot: {err}"))?;
+        replace_file(&snapshot_path(data_dir, &self.game_id), &json)
+    }
+
+    /// Forgets a game that has finished, it doesn't need recovering anymore
+    pub fn remove(data_dir: &Path, game_id: &str) -> Result<(), String> {
+        let path = snapshot_path(data_dir, game_id);
+        match std::fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(format!("could not remove {}: {err}", path.display())),
+        }
+    }
+
+    /// Every snapshot that can be read. One that can't is renamed to `.json.broken` so it is kept
+    /// for a look but doesn't stop the other games from being recovered.
+    fn load_all(data_dir: &Path) -> Result<Vec<GameSnapshot>, String> {
+        let dir = snapshots_dir(data_dir);
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec!()),
+            Err(err) => return Err(format!("could not read {}: {err}", dir.display())),
+        };
+
+        let mut snapshots = vec!();
+        for entry in entries {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(err) => {
+                    warn!(dir = %dir.display(), %err, "Could not read snapshot entry");
+                    continue;
+                }
+            };
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+            match GameSnapshot::load(&path) {
+                Ok(snapshot) => snapshots.push(snapshot),
+                Err(err) => {
+                    error!(%err, "Skipping broken snapshot");
+                    let broken_path = path.with_extension("json.broken");
+                    if let Err(err) = std::fs::rename(&path, &broken_path) {
+                        warn!(path = %path.display(), %err, "Could not set broken snapshot aside");
+                    }
+                }
+            }
+        }
+        Ok(snapshots)
+    }
+
+    fn load(path: &Path) -> Result<GameSnapshot, String> {
+        let bytes = std::fs::read(path).map_err(|err| format!("could not read {}: {err}", path.display()))?;
+        serde_json::from_slice(&bytes).map_err(|err| format!("could not parse {}: {err}", path.display()))
+    }
+}
+
+/// Brings back every game that was running when the server last stopped. Lobbies are reopened
+/// under the same game id and access tokens. Games that had started are settled as interrupted
+/// in the match history, so whatever players spent in them can be refunded.
+pub async fn recover_games(state: &Arc<AllGamesState>) -> Result<(), String> {
+    for snapshot in GameSnapshot::load_all(&state.data_dir)? {
+        let game_id = snapshot.game_id.to_string();
+        // one broken snapshot shouldn't stop the others from being recovered
+        if let Err(err) = recover_game(state, snapshot).await {
+            error!(%game_id, %err, "Could not recover game");
+        }
+    }
+    Ok(())
+}
+
+async fn recover_game(state: &Arc<AllGamesState>, snapshot: GameSnapshot) -> Result<(), String> {
+    if forget_if_recorded(&state.match_history, &state.data_dir, &snapshot.game_id)? {
+        info!(game_id = %snapshot.game_id, "Dropped snapshot of a game already recorded");
+        return Ok(());
+    }
+
+    let GameSnapshot { game_id, fund_id, user_ids, access_tokens, rules, teams, phase } = snapshot;
+    match phase {
+        SnapshotPhase::Lobby => {
+            let board = match &rules.map {
+                Some(map) => Board::load(map)?,
+                None => Board::default(),
+            };
+            let config = GameConfig {
+                game_id: game_id.to_string(),
+                fund_id,
+                access_tokens,
+                power_up_costs: PowerUpCosts::default(),
+                board,
+                teams: if teams.is_empty() {
+                    Teams::default()
+                } else {
+                    Teams::new(teams.into_iter().collect(), &user_ids)?
+                },
+                rules,
+            };
+            start_game(state, user_ids, config).await;
+            info!(%game_id, "Reopened lobby");
+        }
+        SnapshotPhase::Playing { tick, started_at, amounts_spent, stats } => {
+            let record = MatchRecord {
+                game_id: game_id.to_string(),
+                fund_id,
+                result: MatchResult::Interrupted { user_ids: user_ids.clone() },
+                user_ids,
+                teams,
+                rules,
+                started_at,
+                finished_at: unix_time_secs(),
+                duration_ms: tick * TICK_TIME_MS,
+                amounts_spent,
+                stats,
+                replay: None,
+            };
+            let save = state.match_history.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).record(record)?;
+            save.write()?;
+            GameSnapshot::remove(&state.data_dir, &game_id)?;
+            info!(%game_id, "Settled interrupted game");
+        }
+    }
+    Ok(())
+}
+
+/// Removes the snapshot of a game the match history already has. The game finished and was
+/// recorded, but the server stopped before the snapshot was removed, so settling it again would
+/// record it twice.
+fn forget_if_recorded(match_history: &Mutex<MatchHistory>, data_dir: &Path, game_id: &str) -> Result<bool, String> {
+    if !match_history.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).contains(game_id) {
+        return Ok(false);
+    }
+    GameSnapshot::remove(data_dir, game_id)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::management_server::match_history::MatchFilter;
+
+    fn record(game_id: &str) -> MatchRecord {
+        MatchRecord {
+            game_id: game_id.to_string(),
+            fund_id: None,
+            user_ids: vec!("a".to_string(), "b".to_string()),
+            teams: BTreeMap::new(),
+            rules: GameRules::default(),
+            result: MatchResult::Interrupted { user_ids: vec!("a".to_string(), "b".to_string()) },
+            started_at: 0,
+            finished_at: 10,
+            duration_ms: 10_000,
+            amounts_spent: vec!(),
+            stats: vec!(),
+            replay: None,
+        }
+    }
+
+    #[test]
+    fn recovering_a_recorded_game_leaves_the_history_alone() {
+        let data_dir = std::env::temp_dir().join(format!("snapshots-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&data_dir);
+        let mut history = MatchHistory::load(data_dir.join("matches.jsonl")).unwrap();
+        history.record(record("game")).unwrap().write().unwrap();
+        let history = Mutex::new(history);
+
+        let snapshot = GameSnapshot {
+            game_id: "game".to_string(),
+            fund_id: None,
+            user_ids: vec!("a".to_string(), "b".to_string()),
+            access_tokens: HashMap::new(),
+            rules: GameRules::default(),
+            teams: BTreeMap::new(),
+            phase: SnapshotPhase::Playing { tick: 100, started_at: 0, amounts_spent: vec!(), stats: vec!() },
+        };
+        snapshot.save(&data_dir).unwrap();
+
+        assert!(forget_if_recorded(&history, &data_dir, "game").unwrap());
+        assert!(GameSnapshot::load_all(&data_dir).unwrap().is_empty());
+        assert!(!forget_if_recorded(&history, &data_dir, "other").unwrap());
+
+        let mut history = history.into_inner().unwrap();
+        assert!(history.record(record("game")).is_err());
+        let (_, total) = history.query(&MatchFilter::default(), 0, 10);
+        assert_eq!(total, 1);
+        let reloaded = MatchHistory::load(data_dir.join("matches.jsonl")).unwrap();
+        assert_eq!(reloaded.query(&MatchFilter::default(), 0, 10).1, 1);
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::VecDeque;
+use crate::games_server::all_games_state::game_state::{Direction, Snake};
+use crate::games_server::board::Board;
+use crate::games_server::occupancy_grid::OccupancyGrid;
+
+/// How many blocks in front of a moving head are treated as taken
+const PROJECTED_PATH_LEN: u32 = 8;
+/// Clearance beyond which a spawn point is considered just as good as any other
+const MAX_USEFUL_CLEARANCE: u32 = 10;
+
+const DIRECTIONS: [Direction; 4] = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+
+#[derive(Debug, Clone)]
+pub struct SpawnPoint {
+    pub head: (u32, u32),
+    pub direction: Direction,
+}
+
+/// Finds a place to put a new snake of `body_len` blocks, as far away as possible from every
+/// living snake, every apple, every wall and teleport and the path each living head is about to
+/// move along. `occupancy` has to be in sync with `snakes`.
+///
+/// Returns `None` when there is no spot where the new snake's body and the path in front of it
+/// are completely free.
+pub fn find_spawn_point<'a>(
+    board: &Board,
+    occupancy: &OccupancyGrid,
+    snakes: impl IntoIterator<Item = &'a Snake>,
+    apples: &[(u32, u32)],
+    body_len: u32
+) -> Option<SpawnPoint> {
+    let board_size = board.size;
+
+    let mut taken: Vec<(u32, u32)> = occupancy.occupied().collect();
+    for snake in snakes {
+        if let Snake::Alive(snake) = snake
+            && snake.frozen_for.is_none() {
+            let mut point = snake.head;
+            for _ in 0..PROJECTED_PATH_LEN {
+                point = board.step(point, &snake.head_direction);
+                taken.push(point);
+            }
+        }
+    }
+    taken.extend_from_slice(apples);
+    taken.extend(board.walls.iter());
+    taken.extend(board.teleports.keys());
+
+    let clearance = clearance_map(board_size, &taken);
+
+    let mut best_score = 0;
+    let mut best = vec!();
+    for x in 0..board_size.0 {
+        for y in 0..board_size.1 {
+            for direction in &DIRECTIONS {
+                let score = footprint_clearance(&clearance, board_size, (x, y), direction, body_len);
+                if score > best_score {
+                    best_score = score;
+                    best.clear();
+                }
+                if score == best_score && score > 0 {
+                    best.push(SpawnPoint { head: (x, y), direction: direction.clone() });
+                }
+            }
+        }
+    }
+
+    if best.is_empty() {
+        None
+    } else {
+        Some(best.swap_remove(rand::random::<u32>() as usize % best.len()))
+    }
+}
+
+/// Distance from every block to the nearest taken block, walking around the board like a snake
+fn clearance_map(
+    board_size: (u32, u32),
+    taken: &[(u32, u32)]
+) -> Vec<u32> {
+    let index = |(x, y): (u32, u32)| (y * board_size.0 + x) as usize;
+
+    let mut clearance = vec![MAX_USEFUL_CLEARANCE; (board_size.0 * board_size.1) as usize];
+    let mut queue = VecDeque::new();
+    for point in taken {
+        if clearance[index(*point)] != 0 {
+            clearance[index(*point)] = 0;
+            queue.push_back(*point);
+        }
+    }
+
+    while let Some(point) = queue.pop_front() {
+        let next_clearance = clearance[index(point)] + 1;
+        for direction in &DIRECTIONS {
+            let next = direction.step(point, board_size);
+            if clearance[index(next)] > next_clearance {
+                clearance[index(next)] = next_clearance;
+                queue.push_back(next);
+            }
+        }
+    }
+
+    clearance
+}
+
+/// The lowest clearance over the blocks a snake spawned at `head` would cover now and over its
+/// first few moves, or 0 if any of them is taken
+fn footprint_clearance(
+    clearance: &[u32],
+    board_size: (u32, u32),
+    head: (u32, u32),
+    direction: &Direction,
+    body_len: u32
+) -> u32 {
+    let index = |(x, y): (u32, u32)| (y * board_size.0 + x) as usize;
+
+    let mut lowest = clearance[index(head)];
+
+    let mut point = head;
+    for _ in 1..body_len {
+        point = direction.opposite().step(point, board_size);
+        lowest = lowest.min(clearance[index(point)]);
+    }
+
+    let mut point = head;
+    for _ in 0..PROJECTED_PATH_LEN {
+        point = direction.step(point, board_size);
+        lowest = lowest.min(clearance[index(point)]);
+    }
+
+    lowest
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+    use crate::games_server::all_games_state::game_state::AliveSnake;
+
+    /// A board with `rows` as its layout, see [`Board::parse`]
+    fn parse_layout(rows: &[String]) -> Board {
+        Board::parse(&format!("layout = \"\"\"\n{}\n\"\"\"", rows.join("\n"))).unwrap()
+    }
+
+    fn spawn(
+        board: &Board,
+        snakes: Vec<AliveSnake>,
+        apples: &[(u32, u32)],
+        body_len: u32
+    ) -> Option<SpawnPoint> {
+        let snakes: HashMap<String, Snake> = snakes.into_iter()
+            .map(|snake| (snake.user_id.to_string(), Snake::Alive(snake)))
+            .collect();
+        let mut occupancy = OccupancyGrid::new(board.size);
+        occupancy.sync(&snakes, board);
+        find_spawn_point(board, &occupancy, snakes.values(), apples, body_len)
+    }
+
+    /// Every block a snake spawned at `spawn` covers now and over its first moves
+    fn footprint(spawn: &SpawnPoint, board_size: (u32, u32), body_len: u32) -> Vec<(u32, u32)> {
+        let mut blocks = vec!(spawn.head);
+        let mut point = spawn.head;
+        for _ in 1..body_len {
+            point = spawn.direction.opposite().step(point, board_size);
+            blocks.push(point);
+        }
+        let mut point = spawn.head;
+        for _ in 0..PROJECTED_PATH_LEN {
+            point = spawn.direction.step(point, board_size);
+            blocks.push(point);
+        }
+        blocks
+    }
+
+    #[test]
+    fn spawns_clear_of_everything_on_the_board() {
+        let mut rows = vec!(".".repeat(30); 30);
+        rows[5] = format!("{}{}{}", ".".repeat(5), "#".repeat(10), ".".repeat(15));
+        rows[25] = format!("{}A{}", ".".repeat(25), ".".repeat(4));
+        rows[27] = format!("...A{}", ".".repeat(26));
+        let board = parse_layout(&rows);
+        let snake = || AliveSnake::new("a", (15, 15), vec!((Direction::Right, 5)));
+        let apples = [(20, 5), (8, 20)];
+
+        let mut taken: Vec<(u32, u32)> = (11..=15).map(|x| (x, 15)).collect();
+        taken.extend((16..=23).map(|x| (x, 15)));
+        taken.extend(apples);
+        taken.extend(board.walls.iter());
+        taken.extend(board.teleports.keys());
+
+        for _ in 0..50 {
+            let spawned = spawn(&board, vec!(snake()), &apples, 4).unwrap();
+            for point in footprint(&spawned, board.size, 4) {
+                assert!(!taken.contains(&point), "{spawned:?} covers {point:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_off_the_path_in_front_of_a_head() {
+        // only the middle row is free, and the head at its start is about to move along 8 of it
+        let rows = vec!("#".repeat(20), ".".repeat(20), "#".repeat(20));
+        let board = parse_layout(&rows);
+
+        for _ in 0..50 {
+            let spawned = spawn(&board, vec!(AliveSnake::new("a", (0, 1), vec!((Direction::Right, 1)))), &[], 3).unwrap();
+            let spot = (spawned.head, spawned.direction);
+            assert!(spot == ((11, 1), Direction::Right) || spot == ((17, 1), Direction::Left), "{spot:?}");
+        }
+
+        // a frozen head isn't going anywhere
+        let frozen = || {
+            let mut snake = AliveSnake::new("a", (0, 1), vec!((Direction::Right, 1)));
+            snake.frozen_for = Some(10);
+            snake
+        };
+        let spawned: Vec<_> = (0..50).map(|_| spawn(&board, vec!(frozen()), &[], 3).unwrap().head).collect();
+        assert!(spawned.iter().any(|head| head.0 < 9), "{spawned:?}");
+    }
+
+    #[test]
+    fn none_when_nowhere_is_free() {
+        // a snake and the path in front of it don't fit anywhere inside the borders
+        let board = Board::parse("solid_borders = true\nlayout = \"\"\"\n.....\n.....\n.....\n.....\n.....\n\"\"\"").unwrap();
+        assert!(spawn(&board, vec!(), &[], 3).is_none());
+
+        // apples everywhere but a couple of blocks
+        let rows = vec!(".".repeat(20); 3);
+        let board = parse_layout(&rows);
+        let apples: Vec<(u32, u32)> = (0..20).flat_map(|x| [(x, 0), (x, 1), (x, 2)])
+            .filter(|point| *point != (5, 1) && *point != (6, 1))
+            .collect();
+        assert!(spawn(&board, vec!(), &apples, 3).is_none());
+    }
+}
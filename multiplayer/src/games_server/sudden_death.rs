@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+/// Once sudden death starts the arena closes in one ring of walls at a time from the edges, and
+/// snakes can no longer be revived or bought extra lives, so the game is bound to end.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SuddenDeathRules {
+    /// seconds into the game before the arena starts shrinking
+    pub starts_after_secs: u64,
+    /// seconds between each ring of the arena turning into walls
+    #[serde(default = "default_shrink_every_secs")]
+    pub shrink_every_secs: u64,
+}
+
+fn default_shrink_every_secs() -> u64 {
+    2
+}
+
+impl SuddenDeathRules {
+    pub fn has_started(&self, tick: u64, tick_time_ms: u64) -> bool {
+        tick * tick_time_ms >= self.starts_after_secs * 1000
+    }
+
+    /// How many rings of walls should have closed in by `tick`
+    pub fn rings_closed(&self, tick: u64, tick_time_ms: u64) -> u32 {
+        if !self.has_started(tick, tick_time_ms) {
+            return 0;
+        }
+        let since_start_ms = tick * tick_time_ms - self.starts_after_secs * 1000;
+        (since_start_ms / (self.shrink_every_secs.max(1) * 1000)) as u32
+    }
+}
+
+/// Every block `depth` blocks in from the edge of the board, 0 being the outermost ring
+pub fn ring(
+    board_size: (u32, u32),
+    depth: u32
+) -> Vec<(u32, u32)> {
+    let (width, height) = board_size;
+    if depth * 2 >= width || depth * 2 >= height {
+        return vec!();
+    }
+    let (left, top, right, bottom) = (depth, depth, width - 1 - depth, height - 1 - depth);
+
+    let mut cells = vec!();
+    for x in left..=right {
+        cells.push((x, top));
+        if bottom != top {
+            cells.push((x, bottom));
+        }
+    }
+    for y in top + 1..bottom {
+        cells.push((left, y));
+        if right != left {
+            cells.push((right, y));
+        }
+    }
+    cells
+}
@@ -0,0 +1,115 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use crate::games_server::win_conditions::{Decision, MatchResult, Standing};
+
+/// Which team each player is on. A game without teams is every player for themselves.
+#[derive(Debug, Clone, Default)]
+pub struct Teams {
+    /// team name for every player, empty when there are no teams
+    team_of: HashMap<String, String>,
+}
+
+impl Teams {
+    /// Checks that every player in the game is on exactly one team, and that there are at least
+    /// two teams with players on them
+    pub fn new(
+        teams: HashMap<String, Vec<String>>,
+        user_ids: &[String]
+    ) -> Result<Teams, String> {
+        let mut team_of = HashMap::new();
+        for (team, members) in teams {
+            for user_id in members {
+                if !user_ids.contains(&user_id) {
+                    return Err(format!("{user_id} is on team {team} but not in the game"));
+                }
+                if let Some(other_team) = team_of.insert(user_id.to_string(), team.to_string()) {
+                    return Err(format!("{user_id} is on both {team} and {other_team}"));
+                }
+            }
+        }
+        if let Some(user_id) = user_ids.iter().find(|user_id| !team_of.contains_key(*user_id)) {
+            return Err(format!("{user_id} is not on a team"));
+        }
+        let team_count = team_of.values().collect::<HashSet<_>>().len();
+        if team_count < 2 {
+            return Err(format!("a team game needs at least 2 teams with players, not {team_count}"));
+        }
+        Ok(Teams { team_of })
+    }
+
+    pub fn is_team_game(&self) -> bool {
+        !self.team_of.is_empty()
+    }
+
+    /// Whether two different players are on the same team
+    pub fn are_teammates(&self, user_id: &str, other_id: &str) -> bool {
+        user_id != other_id && match (self.team_of.get(user_id), self.team_of.get(other_id)) {
+            (Some(team), Some(other_team)) => team == other_team,
+            _ => false,
+        }
+    }
+
+    /// Every team and its players, sorted by name
+    pub fn members(&self) -> BTreeMap<String, Vec<String>> {
+        let mut members: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (user_id, team) in &self.team_of {
+            members.entry(team.to_string()).or_default().push(user_id.to_string());
+        }
+        for team in members.values_mut() {
+            team.sort();
+        }
+        members
+    }
+
+    /// In a team game, adds up each team's players into one standing named after the team
+    pub fn group(&self, standings: Vec<Standing>) -> Vec<Standing> {
+        if !self.is_team_game() {
+            return standings;
+        }
+        let mut teams: BTreeMap<String, Standing> = BTreeMap::new();
+        for standing in standings {
+            let Some(team) = self.team_of.get(&standing.id) else {
+                continue;
+            };
+            let team_standing = teams.entry(team.to_string()).or_insert(Standing {
+                id: team.to_string(),
+                length: 0,
+                apples_eaten: 0,
+            });
+            team_standing.length += standing.length;
+            team_standing.apples_eaten += standing.apples_eaten;
+        }
+        teams.into_values().collect()
+    }
+
+    /// Turns a decision over standings from [`Teams::group`] into the result of the game
+    pub fn result(&self, decision: Decision) -> MatchResult {
+        if !self.is_team_game() {
+            return match decision {
+                Decision::Best(user_id) => MatchResult::Winner { user_id },
+                Decision::Tied(user_ids) => MatchResult::Draw { user_ids, teams: vec!() },
+            };
+        }
+        let mut members = self.members();
+        match decision {
+            Decision::Best(team) => MatchResult::WinningTeam {
+                user_ids: members.remove(&team).unwrap_or_default(),
+                team,
+            },
+            Decision::Tied(teams) => MatchResult::Draw {
+                user_ids: teams.iter().flat_map(|team| members.remove(team).unwrap_or_default()).collect(),
+                teams,
+            },
+        }
+    }
+
+    /// How much each team has spent in total, sorted by team name
+    pub fn amounts_spent(&self, amounts_spent: &[(String, f64)]) -> Vec<(String, f64)> {
+        let mut totals: BTreeMap<String, f64> = BTreeMap::new();
+        for (user_id, amount) in amounts_spent {
+            if let Some(team) = self.team_of.get(user_id) {
+                *totals.entry(team.to_string()).or_insert(0.0) += amount;
+            }
+        }
+        totals.into_iter().collect()
+    }
+}
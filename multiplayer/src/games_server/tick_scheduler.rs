@@ -0,0 +1,73 @@
+use std::time::Duration;
+use metrics::{counter, histogram};
+use tokio::time::{self, Instant, Interval, MissedTickBehavior};
+use tracing::warn;
+use crate::server_metrics::{TICKS_LATE, TICK_DURATION, TICK_OVERRUNS};
+
+/// What a game does about ticks it missed, like after the host stalls for a moment
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MissedTickPolicy {
+    /// drop the missed ticks and carry on at the normal rate, so the game doesn't speed up
+    #[default]
+    Skip,
+    /// carry on a full tick after each late tick, so every tick from then on is later than planned
+    Delay,
+    /// run the missed ticks back to back until the game has caught up
+    Burst,
+}
+
+impl MissedTickPolicy {
+    pub fn parse(policy: &str) -> Option<MissedTickPolicy> {
+        match policy.to_ascii_lowercase().as_str() {
+            "skip" => Some(MissedTickPolicy::Skip),
+            "delay" => Some(MissedTickPolicy::Delay),
+            "burst" => Some(MissedTickPolicy::Burst),
+            _ => None,
+        }
+    }
+}
+
+/// Runs a game's ticks at a steady rate, and reports ticks that start late or run long
+pub struct TickScheduler {
+    interval: Interval,
+    period: Duration,
+    tick_started: Instant,
+}
+
+impl TickScheduler {
+    pub fn new(period: Duration, policy: MissedTickPolicy) -> TickScheduler {
+        let mut interval = time::interval(period);
+        interval.set_missed_tick_behavior(match policy {
+            MissedTickPolicy::Skip => MissedTickBehavior::Skip,
+            MissedTickPolicy::Delay => MissedTickBehavior::Delay,
+            MissedTickPolicy::Burst => MissedTickBehavior::Burst,
+        });
+        TickScheduler {
+            interval,
+            period,
+            tick_started: Instant::now(),
+        }
+    }
+
+    /// Waits until the next tick is due
+    pub async fn tick(&mut self) {
+        let due = self.interval.tick().await;
+        self.tick_started = Instant::now();
+
+        let late = self.tick_started.saturating_duration_since(due);
+        if late >= self.period {
+            counter!(TICKS_LATE).increment(1);
+            warn!(?late, "Tick started late");
+        }
+    }
+
+    /// Records how long the tick that just ran took
+    pub fn finish(&self) {
+        let tick_duration = self.tick_started.elapsed();
+        histogram!(TICK_DURATION).record(tick_duration.as_secs_f64());
+        if tick_duration > self.period {
+            counter!(TICK_OVERRUNS).increment(1);
+            warn!(?tick_duration, "Tick took longer than the time between ticks");
+        }
+    }
+}
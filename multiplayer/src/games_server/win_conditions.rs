@@ -0,0 +1,290 @@
+use std::cmp::Ordering;
+use serde::{Deserialize, Serialize};
+use crate::games_server::match_stats::PlayerStats;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(tag = "type")]
+pub enum WinCondition {
+    /// the last snake still in the game wins
+    #[default]
+    LastAlive,
+    /// the longest snake when time runs out wins
+    LongestAtTimeLimit { time_limit_secs: u64 },
+    /// whoever has eaten the most apples when time runs out wins
+    MostApples { time_limit_secs: u64 },
+    /// the first snake to grow to `length` blocks wins
+    FirstToLength { length: u32 },
+}
+
+/// How a player, or a whole team, still in the game is doing. Dead snakes waiting on a revive
+/// have length 0.
+#[derive(Debug, Clone)]
+pub struct Standing {
+    /// user id, or team name in a team game
+    pub id: String,
+    pub length: u32,
+    pub apples_eaten: u32,
+}
+
+/// Who a win condition picked, by [`Standing::id`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    Best(String),
+    /// nothing could split these, sorted
+    Tied(Vec<String>),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum MatchResult {
+    Winner { user_id: String },
+    WinningTeam { team: String, user_ids: Vec<String> },
+    /// `teams` is empty unless this is a team game
+    Draw { user_ids: Vec<String>, teams: Vec<String> },
+    /// the server stopped part way through the game, so it has no result and everything
+    /// spent in it is refunded
+    Interrupted { user_ids: Vec<String> },
+}
+
+impl MatchResult {
+    /// The winning player of a game without teams
+    pub fn winner(&self) -> Option<&String> {
+        match self {
+            MatchResult::Winner { user_id } => Some(user_id),
+            MatchResult::WinningTeam { .. } | MatchResult::Draw { .. } | MatchResult::Interrupted { .. } => None,
+        }
+    }
+
+    /// Everyone in the game in finishing order, best first, with tied players grouped together.
+    ///
+    /// Winners come first. Without teams everyone else is ordered by how long they stayed
+    /// alive, in a team game all the losing players share last place.
+    pub fn placings(&self, stats: &[PlayerStats]) -> Vec<Vec<String>> {
+        let (first, is_team_game) = match self {
+            MatchResult::Winner { user_id } => (vec!(user_id.to_string()), false),
+            MatchResult::WinningTeam { user_ids, .. } => (user_ids.clone(), true),
+            MatchResult::Draw { user_ids, teams } => (user_ids.clone(), !teams.is_empty()),
+            MatchResult::Interrupted { user_ids } => return vec!(user_ids.clone()),
+        };
+
+        let mut rest: Vec<&PlayerStats> = stats.iter().filter(|player| !first.contains(&player.user_id)).collect();
+        let mut placings = vec!(first);
+        if is_team_game {
+            if !rest.is_empty() {
+                placings.push(rest.iter().map(|player| player.user_id.to_string()).collect());
+            }
+            return placings;
+        }
+
+        rest.sort_by_key(|player| std::cmp::Reverse(player.time_alive_ms));
+        let mut last_time_alive_ms = None;
+        for player in rest {
+            if last_time_alive_ms == Some(player.time_alive_ms) && let Some(group) = placings.last_mut() {
+                group.push(player.user_id.to_string());
+            } else {
+                placings.push(vec!(player.user_id.to_string()));
+            }
+            last_time_alive_ms = Some(player.time_alive_ms);
+        }
+        placings
+    }
+}
+
+impl WinCondition {
+    /// Decides whether the game is over.
+    ///
+    /// `in_game` is everyone still in the game after this tick and `in_game_before` is everyone
+    /// who was in it when the tick started, which is who the game is decided between if nobody
+    /// survives the tick.
+    pub fn check(
+        &self,
+        tick: u64,
+        tick_time_ms: u64,
+        in_game: &[Standing],
+        in_game_before: &[Standing]
+    ) -> Option<Decision> {
+        if in_game.len() == 1 {
+            return Some(Decision::Best(in_game[0].id.to_string()));
+        }
+        if in_game.is_empty() {
+            return Some(self.best_of(in_game_before));
+        }
+
+        let time_is_up = |time_limit_secs: u64| tick * tick_time_ms >= time_limit_secs * 1000;
+
+        match self {
+            WinCondition::LastAlive => None,
+            WinCondition::LongestAtTimeLimit { time_limit_secs } | WinCondition::MostApples { time_limit_secs } => {
+                if time_is_up(*time_limit_secs) {
+                    Some(self.best_of(in_game))
+                } else {
+                    None
+                }
+            }
+            WinCondition::FirstToLength { length } => {
+                let reached: Vec<_> = in_game.iter().filter(|standing| standing.length >= *length).cloned().collect();
+                if reached.is_empty() {
+                    None
+                } else {
+                    Some(self.best_of(&reached))
+                }
+            }
+        }
+    }
+
+    /// How two players are ranked against each other, the greater one is doing better
+    fn compare(&self, a: &Standing, b: &Standing) -> Ordering {
+        match self {
+            WinCondition::MostApples { .. } => a.apples_eaten.cmp(&b.apples_eaten)
+                .then(a.length.cmp(&b.length)),
+            _ => a.length.cmp(&b.length)
+                .then(a.apples_eaten.cmp(&b.apples_eaten)),
+        }
+    }
+
+    /// The single best player, or everyone tied for first
+    fn best_of(&self, standings: &[Standing]) -> Decision {
+        let Some(best) = standings.iter().max_by(|a, b| self.compare(a, b)) else {
+            return Decision::Tied(vec!());
+        };
+
+        let mut tied: Vec<String> = standings.iter()
+            .filter(|standing| self.compare(standing, best) == Ordering::Equal)
+            .map(|standing| standing.id.to_string())
+            .collect();
+
+        if tied.len() == 1 {
+            Decision::Best(tied.remove(0))
+        } else {
+            tied.sort();
+            Decision::Tied(tied)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK_TIME_MS: u64 = 100;
+
+    fn standing(id: &str, length: u32, apples_eaten: u32) -> Standing {
+        Standing { id: id.to_string(), length, apples_eaten }
+    }
+
+    fn best(id: &str) -> Option<Decision> {
+        Some(Decision::Best(id.to_string()))
+    }
+
+    fn tied(ids: &[&str]) -> Option<Decision> {
+        Some(Decision::Tied(ids.iter().map(|id| id.to_string()).collect()))
+    }
+
+    fn stats(user_id: &str, time_alive_ms: u64) -> PlayerStats {
+        PlayerStats { user_id: user_id.to_string(), time_alive_ms, ..PlayerStats::default() }
+    }
+
+    fn ids(groups: &[&[&str]]) -> Vec<Vec<String>> {
+        groups.iter().map(|group| group.iter().map(|id| id.to_string()).collect()).collect()
+    }
+
+    #[test]
+    fn last_alive_waits_for_one_left() {
+        let both = [standing("a", 3, 0), standing("b", 5, 0)];
+        assert_eq!(WinCondition::LastAlive.check(10_000, TICK_TIME_MS, &both, &both), None);
+        assert_eq!(WinCondition::LastAlive.check(1, TICK_TIME_MS, &both[..1], &both), best("a"));
+    }
+
+    #[test]
+    fn nobody_surviving_is_decided_between_everyone_from_before_the_tick() {
+        let before = [standing("a", 3, 0), standing("b", 5, 0)];
+        assert_eq!(WinCondition::LastAlive.check(1, TICK_TIME_MS, &[], &before), best("b"));
+
+        let before = [standing("a", 5, 1), standing("b", 5, 1)];
+        assert_eq!(WinCondition::LastAlive.check(1, TICK_TIME_MS, &[], &before), tied(&["a", "b"]));
+    }
+
+    #[test]
+    fn longest_at_time_limit() {
+        let condition = WinCondition::LongestAtTimeLimit { time_limit_secs: 10 };
+        let in_game = [standing("a", 3, 4), standing("b", 5, 0)];
+        assert_eq!(condition.check(99, TICK_TIME_MS, &in_game, &in_game), None);
+        assert_eq!(condition.check(100, TICK_TIME_MS, &in_game, &in_game), best("b"));
+
+        // apples break a tie in length
+        let in_game = [standing("a", 5, 4), standing("b", 5, 0)];
+        assert_eq!(condition.check(100, TICK_TIME_MS, &in_game, &in_game), best("a"));
+    }
+
+    #[test]
+    fn most_apples() {
+        let condition = WinCondition::MostApples { time_limit_secs: 10 };
+        let in_game = [standing("a", 3, 4), standing("b", 5, 0)];
+        assert_eq!(condition.check(99, TICK_TIME_MS, &in_game, &in_game), None);
+        assert_eq!(condition.check(100, TICK_TIME_MS, &in_game, &in_game), best("a"));
+
+        // length breaks a tie in apples
+        let in_game = [standing("a", 3, 4), standing("b", 5, 4)];
+        assert_eq!(condition.check(100, TICK_TIME_MS, &in_game, &in_game), best("b"));
+    }
+
+    #[test]
+    fn first_to_length() {
+        let condition = WinCondition::FirstToLength { length: 10 };
+        let in_game = [standing("a", 9, 0), standing("b", 5, 0), standing("c", 2, 0)];
+        assert_eq!(condition.check(1, TICK_TIME_MS, &in_game, &in_game), None);
+
+        let in_game = [standing("a", 10, 0), standing("b", 5, 9), standing("c", 2, 0)];
+        assert_eq!(condition.check(1, TICK_TIME_MS, &in_game, &in_game), best("a"));
+
+        // only those that reached the length are compared
+        let in_game = [standing("a", 10, 0), standing("b", 11, 0), standing("c", 12, 0)];
+        assert_eq!(condition.check(1, TICK_TIME_MS, &in_game[..2], &in_game), best("b"));
+    }
+
+    #[test]
+    fn ties_that_nothing_splits() {
+        let condition = WinCondition::LongestAtTimeLimit { time_limit_secs: 10 };
+        let in_game = [standing("c", 5, 2), standing("a", 5, 2), standing("b", 3, 2)];
+        assert_eq!(condition.check(100, TICK_TIME_MS, &in_game, &in_game), tied(&["a", "c"]));
+    }
+
+    #[test]
+    fn teams_are_decided_by_their_standings() {
+        let condition = WinCondition::LongestAtTimeLimit { time_limit_secs: 10 };
+        let in_game = [standing("red", 12, 0), standing("blue", 9, 0)];
+        assert_eq!(condition.check(100, TICK_TIME_MS, &in_game, &in_game), best("red"));
+        assert_eq!(condition.check(1, TICK_TIME_MS, &in_game[1..], &in_game), best("blue"));
+    }
+
+    #[test]
+    fn placings_order_losers_by_time_alive() {
+        let stats = [stats("a", 100), stats("b", 300), stats("c", 200), stats("d", 200)];
+        let result = MatchResult::Winner { user_id: "b".to_string() };
+        assert_eq!(result.placings(&stats), ids(&[&["b"], &["c", "d"], &["a"]]));
+    }
+
+    #[test]
+    fn placings_of_a_team_game_put_every_loser_last() {
+        let stats = [stats("a", 100), stats("b", 300), stats("c", 200), stats("d", 400)];
+        let result = MatchResult::WinningTeam { team: "red".to_string(), user_ids: vec!("a".to_string(), "b".to_string()) };
+        assert_eq!(result.placings(&stats), ids(&[&["a", "b"], &["c", "d"]]));
+        assert_eq!(result.winner(), None);
+    }
+
+    #[test]
+    fn placings_of_a_draw() {
+        let stats = [stats("a", 100), stats("b", 300), stats("c", 200)];
+        let draw = MatchResult::Draw { user_ids: vec!("b".to_string(), "c".to_string()), teams: vec!() };
+        assert_eq!(draw.placings(&stats), ids(&[&["b", "c"], &["a"]]));
+
+        let team_draw = MatchResult::Draw {
+            user_ids: vec!("a".to_string(), "b".to_string()),
+            teams: vec!("red".to_string(), "blue".to_string()),
+        };
+        assert_eq!(team_draw.placings(&stats[..2]), ids(&[&["a", "b"]]));
+
+        let interrupted = MatchResult::Interrupted { user_ids: vec!("a".to_string(), "b".to_string(), "c".to_string()) };
+        assert_eq!(interrupted.placings(&stats), ids(&[&["a", "b", "c"]]));
+    }
+}
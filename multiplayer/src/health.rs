@@ -0,0 +1,69 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use serde::Serialize;
+use crate::games_server::all_games_state::AllGamesState;
+
+const STORAGE_PROBE_FILE: &str = ".readyz";
+
+#[derive(Serialize)]
+pub struct HealthResponse {
+    status: &'static str,
+}
+
+/// The process is up and answering requests
+pub async fn healthz() -> Json<HealthResponse> {
+    Json(HealthResponse { status: "ok" })
+}
+
+#[derive(Serialize)]
+pub struct ReadinessResponse {
+    ready: bool,
+    listener_bound: bool,
+    /// the data directory can be written to
+    storage_reachable: bool,
+    draining: bool,
+}
+
+/// Whether the server should be sent new games
+pub async fn readyz(
+    State(state): State<Arc<AllGamesState>>
+) -> (StatusCode, Json<ReadinessResponse>) {
+    let listener_bound = state.listener_bound.load(Ordering::Relaxed);
+    let probe = state.data_dir.join(STORAGE_PROBE_FILE);
+    let storage_reachable = tokio::fs::write(&probe, b"ok").await.is_ok()
+        && tokio::fs::remove_file(&probe).await.is_ok();
+    let draining = state.shutdown.is_draining();
+
+    let ready = listener_bound && storage_reachable && !draining;
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(ReadinessResponse { ready, listener_bound, storage_reachable, draining }))
+}
+
+/// Asks the server running at `bind_address` whether it is ready, for container health checks
+/// where there may be no http client installed
+pub fn check_ready(bind_address: &str) -> Result<(), String> {
+    let mut address: SocketAddr = bind_address.parse().map_err(|err| format!("invalid address {bind_address}: {err}"))?;
+    if address.ip().is_unspecified() {
+        address.set_ip([127, 0, 0, 1].into());
+    }
+
+    let timeout = Duration::from_secs(2);
+    let mut stream = TcpStream::connect_timeout(&address, timeout).map_err(|err| format!("could not connect to {address}: {err}"))?;
+    stream.set_read_timeout(Some(timeout)).map_err(|err| err.to_string())?;
+    write!(stream, "GET /readyz HTTP/1.1\r\nHost: {address}\r\nConnection: close\r\n\r\n").map_err(|err| err.to_string())?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).map_err(|err| err.to_string())?;
+    let status_line = response.lines().next().unwrap_or_default();
+    if status_line.split_whitespace().nth(1) == Some("200") {
+        Ok(())
+    } else {
+        Err(format!("not ready: {}", response.lines().last().unwrap_or(status_line)))
+    }
+}
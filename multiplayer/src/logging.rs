@@ -0,0 +1,54 @@
+use std::fmt;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use tracing_subscriber::EnvFilter;
+
+/// How log lines are written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// human readable, for running locally
+    Pretty,
+    /// one json object per line, for log collectors
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(format: &str) -> Option<LogFormat> {
+        match format.to_ascii_lowercase().as_str() {
+            "pretty" => Some(LogFormat::Pretty),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Starts writing logs to stdout. Which ones are written is set by `RUST_LOG`, everything from
+/// info up by default. The websocket library traces every frame it receives, access tokens
+/// included, so it is never logged below debug.
+pub fn init_logging(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new("info"))
+        .add_directive("tungstenite::protocol=debug".parse().expect("valid directive"));
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Pretty => subscriber.init(),
+        LogFormat::Json => subscriber.json().flatten_event(true).with_current_span(true).with_span_list(false).init(),
+    }
+}
+
+/// Logs a secret, like an access token, as a short fingerprint so the same token can be
+/// recognised across log lines without being leaked
+pub struct Redacted<'a>(pub &'a str);
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut hasher = DefaultHasher::new();
+        self.0.hash(&mut hasher);
+        write!(f, "redacted:{:06x}", hasher.finish() & 0xffffff)
+    }
+}
+
+impl fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
@@ -1,10 +1,28 @@
+use crate::health::check_ready;
 use crate::run_server::run_server;
+use crate::server_config::ServerConfig;
 
+pub mod background_writer;
+pub mod directory;
+pub mod fs_util;
 pub mod games_server;
+pub mod health;
+pub mod logging;
 pub mod management_server;
 pub mod run_server;
+pub mod server_config;
+pub mod server_metrics;
 
 #[tokio::main]
 async fn main() {
+    // `healthcheck` checks on the server that is already running instead of starting one
+    if std::env::args().nth(1).as_deref() == Some("healthcheck") {
+        if let Err(err) = check_ready(&ServerConfig::from_env().bind_address) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     run_server().await
 }
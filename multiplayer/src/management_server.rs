@@ -1,3 +1,28 @@
-mod ManagementIncomingMessage;
-mod ManagementOutgoingMessage;
-pub mod create_game;
\ No newline at end of file
+use metrics::counter;
+use crate::server_metrics::AUTH_FAILURES;
+
+pub mod create_game;
+pub mod json_lines;
+pub mod leaderboard;
+pub mod match_history;
+pub mod matches;
+pub mod ratings;
+
+/// Whether a request to the management api comes from the fund server
+pub fn is_authorized(api_token: &str) -> bool {
+    // todo in the future, add proper authentication
+    let authorized = api_token == "secret_token";
+    if !authorized {
+        counter!(AUTH_FAILURES, "reason" => "invalid_api_token").increment(1);
+    }
+    authorized
+}
+
+/// Seconds since the unix epoch
+pub fn unix_time_secs() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
+
+pub fn unix_time_millis() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|time| time.as_millis() as u64).unwrap_or(0)
+}
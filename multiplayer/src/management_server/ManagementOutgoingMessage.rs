@@ -1,7 +0,0 @@
-use serde::Serialize;
-
-#[derive(Debug, Serialize)]
-#[serde(tag = "type")]
-pub enum ManagementOutgoingMessage {
-    
-}
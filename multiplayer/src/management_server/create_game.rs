@@ -1,20 +1,37 @@
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
-use axum::extract::State;
+use axum::extract::{Query, State};
+use axum::http::header::LOCATION;
 use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use axum::Json;
 use serde::{Deserialize, Serialize};
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::{broadcast, mpsc, watch};
+use tracing::{info, info_span, warn, Instrument};
 use uuid::Uuid;
+use crate::directory::{assign_game, game_url, place_game};
 use crate::games_server::all_games_state::{AllGamesState, AuthGameState};
-use crate::games_server::all_games_state::game_state::GameState;
-use crate::games_server::game_runner::game_runner;
+use crate::games_server::all_games_state::game_state::{GamePhase, GameStatus};
+use crate::games_server::board::Board;
+use crate::games_server::game_runner::{game_runner, GameConfig};
+use crate::games_server::game_rules::GameRules;
 use crate::games_server::power_up_cost_loader::PowerUpCosts;
+use crate::games_server::presence::Presence;
+use crate::games_server::teams::Teams;
+use crate::management_server::is_authorized;
 
 #[derive(Deserialize)]
 pub struct CreateGamePayload {
     api_token: String,
-    user_ids: Vec<String>
+    user_ids: Vec<String>,
+    #[serde(default)]
+    rules: GameRules,
+    /// team name to the players on that team, every player must be on exactly one team
+    #[serde(default)]
+    teams: Option<HashMap<String, Vec<String>>>,
+    /// the fund group the game is played for, players get a separate rating within each fund
+    #[serde(default)]
+    fund_id: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -28,54 +45,121 @@ pub struct UserAccessToken {
 pub enum CreateGameResponse {
     Success {
         game_id: String,
-        users: Vec<UserAccessToken>
+        users: Vec<UserAccessToken>,
+        /// where players connect to play the game
+        game_url: String
+    },
+    /// another node has fewer games, so the game should be created there. The response is also
+    /// a redirect to `url`.
+    Moved {
+        url: String
     },
     Error
 }
 
+#[derive(Deserialize)]
+pub struct Placement {
+    /// the node the game was sent to, which creates it without looking for a better one
+    node: Option<String>,
+}
+
 pub async fn create_game(
     State(state): State<Arc<AllGamesState>>,
+    Query(placement): Query<Placement>,
     Json(payload): Json<CreateGamePayload>
-) -> (StatusCode, Json<CreateGameResponse>) {
-    // todo in the future, add proper authentication
-    if payload.api_token != "secret_token" {
-        return (StatusCode::UNAUTHORIZED, Json(CreateGameResponse::Error))
+) -> Response {
+    if !is_authorized(&payload.api_token) {
+        return (StatusCode::UNAUTHORIZED, Json(CreateGameResponse::Error)).into_response()
+    }
+    if state.shutdown.is_draining() {
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(CreateGameResponse::Error)).into_response()
+    }
+
+    if placement.node.as_ref() != Some(&state.node_id)
+        && let Some(node) = place_game(&state).await {
+        let url = format!("{}/create_game?node={}", node.url.trim_end_matches('/'), node.node_id);
+        info!(node_id = %node.node_id, "Sending game to a less loaded node");
+        return (
+            StatusCode::TEMPORARY_REDIRECT,
+            [(LOCATION, url.clone())],
+            Json(CreateGameResponse::Moved { url })
+        ).into_response()
     }
 
-    let auths: HashMap<_, _> = dbg!(payload.user_ids.iter().map(|user_id| (/* todo Uuid::new_v4().to_string()*/user_id.to_string(), user_id.to_string())).collect());
+    let board = match &payload.rules.map {
+        Some(map) => match Board::load(map) {
+            Ok(board) => board,
+            Err(err) => {
+                warn!(%map, %err, "Could not load map");
+                return (StatusCode::BAD_REQUEST, Json(CreateGameResponse::Error)).into_response()
+            }
+        },
+        None => Board::default(),
+    };
+
+    let teams = match payload.teams {
+        Some(teams) => match Teams::new(teams, &payload.user_ids) {
+            Ok(teams) => teams,
+            Err(err) => {
+                warn!(%err, "Could not set up teams");
+                return (StatusCode::BAD_REQUEST, Json(CreateGameResponse::Error)).into_response()
+            }
+        },
+        None => Teams::default(),
+    };
+
+    let auths: HashMap<_, _> = payload.user_ids.iter().map(|user_id| (Uuid::new_v4().to_string(), user_id.to_string())).collect();
     
     let auth_list = auths.iter().map(|(auth_token, user_id)| UserAccessToken {
         access_token: auth_token.to_string(),
         user_id: user_id.to_string(),
     }).collect();
 
+    let game_id = Uuid::new_v4().to_string();
+    let config = GameConfig {
+        game_id: game_id.clone(),
+        fund_id: payload.fund_id,
+        access_tokens: auths,
+        power_up_costs: PowerUpCosts::default(),
+        rules: payload.rules,
+        board,
+        teams,
+    };
+    info!(%game_id, fund_id = ?config.fund_id, players = ?payload.user_ids, "Created game");
+    start_game(&state, payload.user_ids, config).await;
+
+    (StatusCode::OK, Json(CreateGameResponse::Success {
+        game_id,
+        users: auth_list,
+        game_url: game_url(&state.public_url),
+    })).into_response()
+}
+
+/// Opens the lobby of a new game and starts running it
+pub async fn start_game(
+    state: &Arc<AllGamesState>,
+    user_ids: Vec<String>,
+    config: GameConfig
+) {
     let (pass_on_incoming_message, get_incoming_message) = mpsc::channel(100);
     let (send_to_players, get_to_players) = broadcast::channel(100);
 
-    let game_mutex = Arc::new(Mutex::new(GameState::WaitingForPlayers {
-        ready_status: payload.user_ids.iter().map(|user_id| (user_id.to_string(), false)).collect()
-    }));
-    // todo fix
-    let game_id = Uuid::new_v4().to_string();
+    let (status, get_status) = watch::channel(GameStatus { phase: GamePhase::WaitingForPlayers, tick: 0 });
+    let presence = Arc::new(Mutex::new(Presence::default()));
+    let game_id = config.game_id.clone();
     let game_state = AuthGameState {
-        players: auths,
-        game: Arc::clone(&game_mutex),
+        players: config.access_tokens.clone(),
+        status: get_status,
         sender: pass_on_incoming_message,
-        to_players: get_to_players
+        to_players: get_to_players,
+        presence: Arc::clone(&presence),
     };
-    
-    let state_clone = state.clone();
-    tokio::spawn(async move {
-        game_runner(game_mutex, payload.user_ids, get_incoming_message, send_to_players, state_clone, PowerUpCosts::default()).await;
-    });
 
-    {
-        let mut writer = state.games.write().await;
-        writer.insert(game_id.clone(), game_state);
-    }
+    let state_clone = state.clone();
+    state.game_workers.spawn(state.games.shard_index(&game_id), async move {
+        game_runner(status, presence, user_ids, get_incoming_message, send_to_players, state_clone, config).await;
+    }.instrument(info_span!("game", %game_id)));
 
-    (StatusCode::OK, Json(CreateGameResponse::Success {
-        game_id,
-        users: auth_list,
-    }))
+    assign_game(state, &game_id).await;
+    state.games.insert(game_id, game_state).await;
 }
@@ -0,0 +1,125 @@
+use std::io::Write;
+use std::path::Path;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::warn;
+
+/// Reads a file of one json value per line, an empty list if it doesn't exist yet.
+///
+/// A crash part way through appending can only leave the last line without its newline, so that
+/// line is repaired before anything else is appended: the newline is added if the value is
+/// complete, otherwise the line is dropped.
+pub fn read_json_lines<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>, String> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec!()),
+        Err(err) => return Err(format!("could not read {}: {err}", path.display())),
+    };
+
+    let complete_len = contents.rfind('\n').map_or(0, |newline| newline + 1);
+    let (complete, unfinished) = contents.split_at(complete_len);
+
+    let mut values = vec!();
+    for (line_number, line) in complete.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value = serde_json::from_str(line)
+            .map_err(|err| format!("could not parse line {} of {}: {err}", line_number + 1, path.display()))?;
+        values.push(value);
+    }
+
+    if !unfinished.trim().is_empty() {
+        // whatever is appended next has to start on a line of its own
+        let repaired = match serde_json::from_str(unfinished) {
+            Ok(value) => {
+                values.push(value);
+                std::fs::OpenOptions::new()
+                    .append(true)
+                    .open(path)
+                    .and_then(|mut file| file.write_all(b"\n"))
+            }
+            Err(err) => {
+                warn!(path = %path.display(), %err, "Dropping unfinished last line");
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(path)
+                    .and_then(|file| file.set_len(complete_len as u64))
+            }
+        };
+        repaired.map_err(|err| format!("could not repair {}: {err}", path.display()))?;
+    }
+
+    Ok(values)
+}
+
+/// `values` as json lines, ready to be appended with [`append`]
+pub fn to_json_lines<T: Serialize>(values: &[T]) -> Result<Vec<u8>, String> {
+    let mut lines = vec!();
+    for value in values {
+        serde_json::to_writer(&mut lines, value).map_err(|err| format!("could not serialize line: {err}"))?;
+        lines.push(b'\n');
+    }
+    Ok(lines)
+}
+
+/// Adds `lines` to the end of the file, creating it and its directory if needed
+pub fn append(path: &Path, lines: &[u8]) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|err| format!("could not create {}: {err}", dir.display()))?;
+    }
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(lines))
+        .map_err(|err| format!("could not write {}: {err}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A file of its own for each test, as they run side by side
+    fn test_file(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("json-lines-{name}-{}.jsonl", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn missing_file_is_empty() {
+        let path = std::env::temp_dir().join(format!("json-lines-missing-{}.jsonl", std::process::id()));
+        assert_eq!(read_json_lines::<u32>(&path).unwrap(), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn drops_a_truncated_last_line() {
+        let path = test_file("truncated", "[1]\n[2]\n[3, 4");
+        assert_eq!(read_json_lines::<Vec<u32>>(&path).unwrap(), vec!(vec!(1), vec!(2)));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "[1]\n[2]\n");
+
+        append(&path, &to_json_lines(&[vec!(5)]).unwrap()).unwrap();
+        assert_eq!(read_json_lines::<Vec<u32>>(&path).unwrap(), vec!(vec!(1), vec!(2), vec!(5)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn keeps_a_complete_last_line_missing_its_newline() {
+        let path = test_file("no-newline", "[1]\n[2]");
+        assert_eq!(read_json_lines::<Vec<u32>>(&path).unwrap(), vec!(vec!(1), vec!(2)));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "[1]\n[2]\n");
+
+        append(&path, &to_json_lines(&[vec!(3)]).unwrap()).unwrap();
+        assert_eq!(read_json_lines::<Vec<u32>>(&path).unwrap(), vec!(vec!(1), vec!(2), vec!(3)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_broken_line_before_the_last_is_an_error() {
+        let path = test_file("broken", "[1]\n[2\n[3]\n");
+        let err = read_json_lines::<Vec<u32>>(&path).unwrap_err();
+        assert!(err.starts_with("could not parse line 2"), "{err}");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
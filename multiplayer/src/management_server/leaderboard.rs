@@ -0,0 +1,83 @@
+use std::sync::Arc;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use crate::games_server::all_games_state::AllGamesState;
+use crate::management_server::is_authorized;
+use crate::management_server::ratings::{PlayerRating, RatingChange};
+
+const DEFAULT_LEADERBOARD_SIZE: usize = 100;
+const MAX_LEADERBOARD_SIZE: usize = 500;
+
+#[derive(Deserialize)]
+pub struct LeaderboardQuery {
+    api_token: String,
+    /// only rank players within this fund group, otherwise across every game
+    fund_id: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type")]
+pub enum LeaderboardResponse {
+    Success {
+        /// best rated first
+        players: Vec<PlayerRating>
+    },
+    Error
+}
+
+pub async fn leaderboard(
+    State(state): State<Arc<AllGamesState>>,
+    Query(query): Query<LeaderboardQuery>
+) -> (StatusCode, Json<LeaderboardResponse>) {
+    if !is_authorized(&query.api_token) {
+        return (StatusCode::UNAUTHORIZED, Json(LeaderboardResponse::Error))
+    }
+
+    let limit = query.limit.unwrap_or(DEFAULT_LEADERBOARD_SIZE).min(MAX_LEADERBOARD_SIZE);
+    let ratings = state.ratings.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let players = ratings.leaderboard(query.fund_id.as_ref(), limit);
+
+    (StatusCode::OK, Json(LeaderboardResponse::Success { players }))
+}
+
+#[derive(Deserialize)]
+pub struct PlayerRatingsQuery {
+    api_token: String,
+    /// also give the player's rating within this fund group, and only its games in the history
+    fund_id: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type")]
+pub enum PlayerRatingsResponse {
+    Success {
+        /// missing until the player has finished a game
+        global: Option<PlayerRating>,
+        fund: Option<PlayerRating>,
+        /// oldest first
+        history: Vec<RatingChange>,
+    },
+    Error
+}
+
+pub async fn player_ratings(
+    State(state): State<Arc<AllGamesState>>,
+    Path(user_id): Path<String>,
+    Query(query): Query<PlayerRatingsQuery>
+) -> (StatusCode, Json<PlayerRatingsResponse>) {
+    if !is_authorized(&query.api_token) {
+        return (StatusCode::UNAUTHORIZED, Json(PlayerRatingsResponse::Error))
+    }
+
+    let ratings = state.ratings.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let fund_id = query.fund_id.as_ref();
+
+    (StatusCode::OK, Json(PlayerRatingsResponse::Success {
+        global: ratings.rating(&user_id, None),
+        fund: fund_id.and_then(|fund_id| ratings.rating(&user_id, Some(fund_id))),
+        history: ratings.history(&user_id, fund_id),
+    }))
+}
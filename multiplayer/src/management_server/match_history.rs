@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use crate::games_server::game_rules::GameRules;
+use crate::games_server::match_stats::PlayerStats;
+use crate::games_server::server_message::AmountSpent;
+use crate::games_server::win_conditions::MatchResult;
+use crate::management_server::json_lines::{append, read_json_lines, to_json_lines};
+
+/// Everything worth keeping about a finished game
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MatchRecord {
+    pub game_id: String,
+    pub fund_id: Option<String>,
+    pub user_ids: Vec<String>,
+    /// team name to its players, empty when there were no teams
+    pub teams: BTreeMap<String, Vec<String>>,
+    pub rules: GameRules,
+    pub result: MatchResult,
+    /// unix time in seconds
+    pub started_at: u64,
+    pub finished_at: u64,
+    /// game time from the first tick to the last
+    pub duration_ms: u64,
+    pub amounts_spent: Vec<AmountSpent>,
+    pub stats: Vec<PlayerStats>,
+    /// where a replay of the game is kept, none are recorded yet
+    pub replay: Option<String>,
+}
+
+/// Which matches to list, every set field has to match
+#[derive(Debug, Default)]
+pub struct MatchFilter {
+    pub user_id: Option<String>,
+    pub fund_id: Option<String>,
+    /// only matches that finished at or after this unix time
+    pub from: Option<u64>,
+    /// only matches that finished before this unix time
+    pub to: Option<u64>,
+}
+
+impl MatchFilter {
+    fn matches(&self, record: &MatchRecord) -> bool {
+        self.user_id.as_ref().is_none_or(|user_id| record.user_ids.contains(user_id))
+            && self.fund_id.as_ref().is_none_or(|fund_id| record.fund_id.as_ref() == Some(fund_id))
+            && self.from.is_none_or(|from| record.finished_at >= from)
+            && self.to.is_none_or(|to| record.finished_at < to)
+    }
+}
+
+/// Every finished match, oldest first. Kept on disk as one json line per match so recording a
+/// match never rewrites the ones before it.
+#[derive(Debug, Default)]
+pub struct MatchHistory {
+    matches: Vec<MatchRecord>,
+    path: PathBuf,
+}
+
+impl MatchHistory {
+    /// Loads the matches saved at `path`, starting from nothing if there aren't any yet
+    pub fn load(path: PathBuf) -> Result<MatchHistory, String> {
+        let matches = read_json_lines(&path)?;
+        Ok(MatchHistory { matches, path })
+    }
+
+    /// Adds a finished match to the history. Returns what has to be written to disk, so it can
+    /// be written without holding up anyone querying the history. A game is only ever recorded once.
+    pub fn record(&mut self, record: MatchRecord) -> Result<MatchSave, String> {
+        if self.contains(&record.game_id) {
+            return Err(format!("game {} is already recorded", record.game_id));
+        }
+        let line = to_json_lines(std::slice::from_ref(&record));
+
+        // keep the match queryable even if it couldn't be saved
+        self.matches.push(record);
+        Ok(MatchSave {
+            path: self.path.clone(),
+            line: line?,
+        })
+    }
+
+    pub fn contains(&self, game_id: &str) -> bool {
+        self.matches.iter().any(|record| record.game_id == game_id)
+    }
+
+    /// One page of the matches passing `filter`, newest first, and how many pass it in total
+    pub fn query(&self, filter: &MatchFilter, offset: usize, limit: usize) -> (Vec<MatchRecord>, usize) {
+        let matching: Vec<_> = self.matches.iter().rev().filter(|record| filter.matches(record)).collect();
+        let total = matching.len();
+        let page = matching.into_iter().skip(offset).take(limit).cloned().collect();
+        (page, total)
+    }
+}
+
+/// A recorded match, ready to be appended to the history file
+#[derive(Debug)]
+pub struct MatchSave {
+    path: PathBuf,
+    line: Vec<u8>,
+}
+
+impl MatchSave {
+    pub fn write(&self) -> Result<(), String> {
+        append(&self.path, &self.line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(game_id: &str, user_ids: &[&str], fund_id: Option<&str>, finished_at: u64) -> MatchRecord {
+        let user_ids: Vec<String> = user_ids.iter().map(|user_id| user_id.to_string()).collect();
+        MatchRecord {
+            game_id: game_id.to_string(),
+            fund_id: fund_id.map(str::to_string),
+            result: MatchResult::Interrupted { user_ids: user_ids.clone() },
+            user_ids,
+            teams: BTreeMap::new(),
+            rules: GameRules::default(),
+            started_at: finished_at - 60,
+            finished_at,
+            duration_ms: 60_000,
+            amounts_spent: vec!(),
+            stats: vec!(),
+            replay: None,
+        }
+    }
+
+    fn history() -> MatchHistory {
+        let mut history = MatchHistory::default();
+        for (game_id, user_ids, fund_id, finished_at) in [
+            ("1", &["a", "b"][..], Some("fund"), 100),
+            ("2", &["b", "c"][..], None, 200),
+            ("3", &["a", "c"][..], Some("fund"), 300),
+            ("4", &["a", "b", "c"][..], Some("other"), 400),
+            ("5", &["a"][..], Some("fund"), 500),
+        ] {
+            history.record(record(game_id, user_ids, fund_id, finished_at)).unwrap();
+        }
+        history
+    }
+
+    fn game_ids(page: &[MatchRecord]) -> Vec<&str> {
+        page.iter().map(|record| record.game_id.as_str()).collect()
+    }
+
+    #[test]
+    fn pages_newest_first() {
+        let history = history();
+        let (page, total) = history.query(&MatchFilter::default(), 0, 2);
+        assert_eq!((game_ids(&page), total), (vec!("5", "4"), 5));
+        let (page, total) = history.query(&MatchFilter::default(), 4, 2);
+        assert_eq!((game_ids(&page), total), (vec!("1"), 5));
+    }
+
+    #[test]
+    fn paging_past_the_end_is_empty_but_keeps_the_total() {
+        let history = history();
+        let (page, total) = history.query(&MatchFilter::default(), 5, 10);
+        assert_eq!((game_ids(&page), total), (vec!(), 5));
+        let (page, total) = history.query(&MatchFilter::default(), usize::MAX, 10);
+        assert_eq!((game_ids(&page), total), (vec!(), 5));
+        let (page, total) = history.query(&MatchFilter::default(), 0, 0);
+        assert_eq!((game_ids(&page), total), (vec!(), 5));
+    }
+
+    #[test]
+    fn filters_before_paging() {
+        let history = history();
+        let filter = MatchFilter { user_id: Some("a".to_string()), fund_id: Some("fund".to_string()), ..MatchFilter::default() };
+        let (page, total) = history.query(&filter, 1, 10);
+        assert_eq!((game_ids(&page), total), (vec!("3", "1"), 3));
+
+        let filter = MatchFilter { from: Some(200), to: Some(400), ..MatchFilter::default() };
+        let (page, total) = history.query(&filter, 0, 10);
+        assert_eq!((game_ids(&page), total), (vec!("3", "2"), 2));
+
+        let filter = MatchFilter { user_id: Some("d".to_string()), ..MatchFilter::default() };
+        assert_eq!(history.query(&filter, 0, 10).1, 0);
+    }
+
+    #[test]
+    fn records_each_game_once() {
+        let mut history = history();
+        assert!(history.record(record("3", &["a"], None, 600)).is_err());
+        assert_eq!(history.query(&MatchFilter::default(), 0, 10).1, 5);
+    }
+}
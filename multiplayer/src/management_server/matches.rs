@@ -0,0 +1,60 @@
+use std::sync::Arc;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use crate::games_server::all_games_state::AllGamesState;
+use crate::management_server::is_authorized;
+use crate::management_server::match_history::{MatchFilter, MatchRecord};
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
+#[derive(Deserialize)]
+pub struct MatchesQuery {
+    api_token: String,
+    /// only matches this player was in
+    user_id: Option<String>,
+    fund_id: Option<String>,
+    /// unix time in seconds, matches finished at or after it
+    from: Option<u64>,
+    /// unix time in seconds, matches finished before it
+    to: Option<u64>,
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type")]
+pub enum MatchesResponse {
+    Success {
+        /// newest first
+        matches: Vec<MatchRecord>,
+        /// how many matches pass the filter across every page
+        total: usize,
+    },
+    Error
+}
+
+pub async fn matches(
+    State(state): State<Arc<AllGamesState>>,
+    Query(query): Query<MatchesQuery>
+) -> (StatusCode, Json<MatchesResponse>) {
+    if !is_authorized(&query.api_token) {
+        return (StatusCode::UNAUTHORIZED, Json(MatchesResponse::Error))
+    }
+
+    let filter = MatchFilter {
+        user_id: query.user_id,
+        fund_id: query.fund_id,
+        from: query.from,
+        to: query.to,
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+
+    let history = state.match_history.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let (matches, total) = history.query(&filter, query.offset, limit);
+
+    (StatusCode::OK, Json(MatchesResponse::Success { matches, total }))
+}
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use crate::fs_util::replace_file;
+use crate::games_server::match_stats::PlayerStats;
+use crate::games_server::teams::Teams;
+use crate::games_server::win_conditions::MatchResult;
+use crate::management_server::json_lines::{append, read_json_lines, to_json_lines};
+use crate::management_server::unix_time_secs;
+
+const INITIAL_RATING: f64 = 1500.0;
+/// the most a player's rating can move in one game
+const K_FACTOR: f64 = 32.0;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlayerRating {
+    pub user_id: String,
+    pub rating: f64,
+    pub games_played: u32,
+    pub wins: u32,
+}
+
+/// How one game moved a player's ratings
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RatingChange {
+    pub game_id: String,
+    pub fund_id: Option<String>,
+    /// unix time in seconds
+    pub finished_at: u64,
+    pub global_before: f64,
+    pub global_after: f64,
+    /// the player's rating within the fund, when the game was played for one
+    pub fund_before: Option<f64>,
+    pub fund_after: Option<f64>,
+}
+
+/// One line of the rating history file
+#[derive(Debug, Serialize, Deserialize)]
+struct HistoryEntry {
+    user_id: String,
+    #[serde(flatten)]
+    change: RatingChange,
+}
+
+/// Elo ratings for every player, overall and within each fund group, saved to disk after every
+/// game. The current ratings are rewritten each time, the history of every change is only ever
+/// appended to a file of its own next to them.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct RatingStore {
+    global: HashMap<String, PlayerRating>,
+    /// fund id to the ratings of players within that fund
+    funds: HashMap<String, HashMap<String, PlayerRating>>,
+    /// user id to every rating change, oldest first, read from the history file
+    #[serde(skip)]
+    history: HashMap<String, Vec<RatingChange>>,
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    history_path: PathBuf,
+}
+
+impl RatingStore {
+    /// Loads the ratings saved at `path`, starting from nothing if there aren't any yet
+    pub fn load(path: PathBuf) -> Result<RatingStore, String> {
+        let mut store = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|err| format!("could not parse {}: {err}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => RatingStore::default(),
+            Err(err) => return Err(format!("could not read {}: {err}", path.display())),
+        };
+        store.history_path = path.with_extension("history.jsonl");
+        store.path = path;
+
+        let entries: Vec<HistoryEntry> = read_json_lines(&store.history_path)?;
+        store.add_history(entries);
+        Ok(store)
+    }
+
+    fn add_history(&mut self, entries: impl IntoIterator<Item = HistoryEntry>) {
+        for entry in entries {
+            self.history.entry(entry.user_id).or_default().push(entry.change);
+        }
+    }
+
+    fn to_json(&self) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(self).map_err(|err| format!("could not serialize ratings: {err}"))
+    }
+
+    /// Updates everyone's ratings from a finished game. Teammates are never rated against each
+    /// other. Returns what has to be written to disk, to be handed to a
+    /// [`BackgroundWriter`](crate::background_writer::BackgroundWriter).
+    pub fn record_game(
+        &mut self,
+        game_id: &str,
+        fund_id: Option<&String>,
+        result: &MatchResult,
+        stats: &[PlayerStats],
+        teams: &Teams
+    ) -> Result<RatingsSave, String> {
+        let placings = result.placings(stats);
+        let winners = match result {
+            MatchResult::Draw { .. } | MatchResult::Interrupted { .. } => vec!(),
+            MatchResult::Winner { .. } | MatchResult::WinningTeam { .. } => placings[0].clone(),
+        };
+        let finished_at = unix_time_secs();
+
+        let global_changes = apply_elo(&mut self.global, &placings, &winners, teams);
+        let fund_changes = fund_id.map(|fund_id| {
+            apply_elo(self.funds.entry(fund_id.to_string()).or_default(), &placings, &winners, teams)
+        });
+
+        let mut entries = vec!();
+        for (user_id, (global_before, global_after)) in global_changes {
+            let fund_change = fund_changes.as_ref().and_then(|changes| changes.get(&user_id));
+            entries.push(HistoryEntry {
+                user_id,
+                change: RatingChange {
+                    game_id: game_id.to_string(),
+                    fund_id: fund_id.cloned(),
+                    finished_at,
+                    global_before,
+                    global_after,
+                    fund_before: fund_change.map(|(before, _)| *before),
+                    fund_after: fund_change.map(|(_, after)| *after),
+                },
+            });
+        }
+
+        let save = RatingsSave {
+            path: self.path.clone(),
+            ratings: self.to_json()?,
+            history_path: self.history_path.clone(),
+            history: to_json_lines(&entries)?,
+        };
+        self.add_history(entries);
+        Ok(save)
+    }
+
+    /// The best rated players, overall or within a fund
+    pub fn leaderboard(&self, fund_id: Option<&String>, limit: usize) -> Vec<PlayerRating> {
+        let ratings = match fund_id {
+            Some(fund_id) => match self.funds.get(fund_id) {
+                Some(ratings) => ratings,
+                None => return vec!(),
+            },
+            None => &self.global,
+        };
+        let mut leaderboard: Vec<_> = ratings.values().cloned().collect();
+        leaderboard.sort_by(|a, b| b.rating.total_cmp(&a.rating).then(a.user_id.cmp(&b.user_id)));
+        leaderboard.truncate(limit);
+        leaderboard
+    }
+
+    pub fn rating(&self, user_id: &str, fund_id: Option<&String>) -> Option<PlayerRating> {
+        match fund_id {
+            Some(fund_id) => self.funds.get(fund_id)?.get(user_id).cloned(),
+            None => self.global.get(user_id).cloned(),
+        }
+    }
+
+    /// Every rating change for a player, oldest first, only for games in `fund_id` if given
+    pub fn history(&self, user_id: &str, fund_id: Option<&String>) -> Vec<RatingChange> {
+        self.history.get(user_id).map(|history| history.iter()
+            .filter(|change| fund_id.is_none() || change.fund_id.as_ref() == fund_id)
+            .cloned()
+            .collect()
+        ).unwrap_or_default()
+    }
+
+}
+
+/// Everything one game changed about the ratings, ready to be written to disk
+#[derive(Debug)]
+pub struct RatingsSave {
+    path: PathBuf,
+    ratings: Vec<u8>,
+    history_path: PathBuf,
+    /// json lines to add to the history
+    history: Vec<u8>,
+}
+
+impl RatingsSave {
+    pub fn write(&self) -> Result<(), String> {
+        append(&self.history_path, &self.history)?;
+        replace_file(&self.path, &self.ratings)
+    }
+}
+
+/// Rates every pair of players in the game against each other, scaled so a game with many
+/// players moves ratings about as much as a one on one game. Returns each player's rating before
+/// and after.
+fn apply_elo(
+    ratings: &mut HashMap<String, PlayerRating>,
+    placings: &[Vec<String>],
+    winners: &[String],
+    teams: &Teams
+) -> HashMap<String, (f64, f64)> {
+    let players: Vec<(&String, usize)> = placings.iter().enumerate()
+        .flat_map(|(place, group)| group.iter().map(move |user_id| (user_id, place)))
+        .collect();
+
+    let before: HashMap<&String, f64> = players.iter().map(|(user_id, _)| {
+        (*user_id, ratings.get(*user_id).map(|rating| rating.rating).unwrap_or(INITIAL_RATING))
+    }).collect();
+
+    let mut changes = HashMap::new();
+    for (user_id, place) in &players {
+        let mut delta = 0.0;
+        let mut opponents = 0;
+        for (other_id, other_place) in &players {
+            if user_id == other_id || teams.are_teammates(user_id, other_id) {
+                continue;
+            }
+            let expected = 1.0 / (1.0 + 10f64.powf((before[other_id] - before[user_id]) / 400.0));
+            let score = match place.cmp(other_place) {
+                std::cmp::Ordering::Less => 1.0,
+                std::cmp::Ordering::Equal => 0.5,
+                std::cmp::Ordering::Greater => 0.0,
+            };
+            delta += score - expected;
+            opponents += 1;
+        }
+
+        let rating_before = before[user_id];
+        let rating_after = if opponents == 0 {
+            rating_before
+        } else {
+            rating_before + K_FACTOR * delta / opponents as f64
+        };
+        let rating = ratings.entry(user_id.to_string()).or_insert(PlayerRating {
+            user_id: user_id.to_string(),
+            rating: INITIAL_RATING,
+            games_played: 0,
+            wins: 0,
+        });
+        rating.rating = rating_after;
+        rating.games_played += 1;
+        if winners.contains(user_id) {
+            rating.wins += 1;
+        }
+        changes.insert(user_id.to_string(), (rating_before, rating_after));
+    }
+    changes
+}
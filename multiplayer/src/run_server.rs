@@ -1,24 +1,113 @@
-use std::sync::Arc;
-use axum::http::Method;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use axum::Router;
 use axum::routing::{get, post};
-use tokio::sync::RwLock;
 use tower_http::cors::{Any, CorsLayer};
+use tracing::info;
+use crate::background_writer::BackgroundWriter;
+use crate::directory::{announce_node, FileDirectory, GameDirectory, LocalDirectory};
 use crate::games_server::all_games_state::AllGamesState;
 use crate::games_server::client_connection::handle_client_connection;
+use crate::games_server::game_registry::GameRegistry;
+use crate::games_server::game_workers::GameWorkers;
+use crate::games_server::shutdown::{shutdown_signal, Shutdown};
+use crate::games_server::snapshots::recover_games;
 use crate::management_server::create_game::create_game;
+use crate::management_server::leaderboard::{leaderboard, player_ratings};
+use crate::management_server::match_history::MatchHistory;
+use crate::management_server::matches::matches;
+use crate::management_server::ratings::RatingStore;
+use crate::health::{healthz, readyz};
+use crate::logging::init_logging;
+use crate::server_config::ServerConfig;
+use crate::server_metrics;
+use crate::server_metrics::install_recorder;
 
 pub async fn run_server() {
+    let config = ServerConfig::from_env();
+    init_logging(config.log_format);
+    let public_url = match config.public_url() {
+        Ok(public_url) => public_url,
+        Err(err) => panic!("Error in server config: {}", err),
+    };
+    let metrics = match install_recorder() {
+        Ok(metrics) => metrics,
+        Err(err) => panic!("Error setting up metrics: {}", err),
+    };
+
+    if let Err(err) = std::fs::create_dir_all(&config.data_dir) {
+        panic!("Error creating data directory {}: {}", config.data_dir.display(), err);
+    }
+
+    let ratings = match RatingStore::load(config.data_dir.join("ratings.json")) {
+        Ok(ratings) => ratings,
+        Err(err) => panic!("Error loading ratings: {}", err),
+    };
+    let match_history = match MatchHistory::load(config.data_dir.join("matches.jsonl")) {
+        Ok(match_history) => match_history,
+        Err(err) => panic!("Error loading match history: {}", err),
+    };
+
+    let game_workers = match GameWorkers::start(config.game_worker_threads) {
+        Ok(game_workers) => game_workers,
+        Err(err) => panic!("Error starting game workers: {}", err),
+    };
+
+    let directory: Box<dyn GameDirectory> = match &config.directory_dir {
+        Some(path) => match FileDirectory::new(path.clone()) {
+            Ok(directory) => Box::new(directory),
+            Err(err) => panic!("Error opening game directory: {}", err),
+        },
+        None => Box::new(LocalDirectory::default()),
+    };
+
     let state = Arc::new(AllGamesState {
-        games: RwLock::new(std::collections::HashMap::new())
+        data_dir: config.data_dir.clone(),
+        games: GameRegistry::default(),
+        game_workers,
+        ratings: Mutex::new(ratings),
+        ratings_writer: BackgroundWriter::start("ratings"),
+        match_history: Mutex::new(match_history),
+        snapshot_writer: BackgroundWriter::start("snapshots"),
+        shutdown: Shutdown::default(),
+        metrics,
+        listener_bound: AtomicBool::new(false),
+        missed_tick_policy: config.missed_tick_policy,
+        node_id: config.node_id.clone(),
+        public_url,
+        directory,
     });
 
+    if let Err(err) = recover_games(&state).await {
+        panic!("Error recovering games: {}", err);
+    }
+    tokio::spawn(announce_node(state.clone()));
+
     let app = Router::new()
         .route("/game", get(handle_client_connection))
         .route("/create_game", post(create_game))
-        .with_state(state)
+        .route("/leaderboard", get(leaderboard))
+        .route("/players/{user_id}/ratings", get(player_ratings))
+        .route("/matches", get(matches))
+        .route("/metrics", get(server_metrics::metrics))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(state.clone())
         .layer(CorsLayer::new().allow_methods(Any).allow_origin(Any).allow_headers(Any));
 
-    let listener = tokio::net::TcpListener::bind("167.96.127.87:3001").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    let listener = tokio::net::TcpListener::bind(&config.bind_address).await.unwrap();
+    state.listener_bound.store(true, Ordering::Relaxed);
+    info!(address = %listener.local_addr().unwrap(), "Listening");
+    let drained = state.clone();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            drained.shutdown.drain(config.shutdown_grace_period).await;
+        })
+        .await
+        .unwrap();
+
+    // the last games to finish or be cut off may still have ratings and snapshots waiting to be written
+    state.ratings_writer.flush().await;
+    state.snapshot_writer.flush().await;
 }
@@ -0,0 +1,69 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::games_server::tick_scheduler::MissedTickPolicy;
+use crate::logging::LogFormat;
+
+/// Settings for the whole server, read from environment variables
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// address and port to listen on, `BIND_ADDRESS`
+    pub bind_address: String,
+    /// where ratings and other persistent data are kept, `DATA_DIR`
+    pub data_dir: PathBuf,
+    /// how long games in progress get to finish when the server is shutting down, `SHUTDOWN_GRACE_SECS`
+    pub shutdown_grace_period: Duration,
+    /// `LOG_FORMAT`, pretty or json
+    pub log_format: LogFormat,
+    /// `MISSED_TICK_POLICY`, skip, delay or burst
+    pub missed_tick_policy: MissedTickPolicy,
+    /// `GAME_WORKER_THREADS`, threads set aside for running games, 0 runs them alongside everything else
+    pub game_worker_threads: usize,
+    /// `NODE_ID`, what this server is called in the directory, the host name by default
+    pub node_id: String,
+    /// `PUBLIC_URL`, the http address other nodes and clients reach this server at, see
+    /// [`ServerConfig::public_url`]
+    pub public_url: Option<String>,
+    /// `DIRECTORY_DIR`, a folder shared by every node to keep track of where games are. Without
+    /// it this server only knows about its own games.
+    pub directory_dir: Option<PathBuf>,
+}
+
+impl ServerConfig {
+    pub fn from_env() -> ServerConfig {
+        let bind_address = std::env::var("BIND_ADDRESS").unwrap_or_else(|_| "167.96.127.87:3001".to_string());
+        ServerConfig {
+            public_url: std::env::var("PUBLIC_URL").ok(),
+            bind_address,
+            data_dir: std::env::var("DATA_DIR").unwrap_or_else(|_| "data".to_string()).into(),
+            shutdown_grace_period: Duration::from_secs(
+                std::env::var("SHUTDOWN_GRACE_SECS").ok().and_then(|secs| secs.parse().ok()).unwrap_or(60)
+            ),
+            log_format: std::env::var("LOG_FORMAT").ok()
+                .and_then(|format| LogFormat::parse(&format))
+                .unwrap_or(LogFormat::Pretty),
+            missed_tick_policy: std::env::var("MISSED_TICK_POLICY").ok()
+                .and_then(|policy| MissedTickPolicy::parse(&policy))
+                .unwrap_or_default(),
+            game_worker_threads: std::env::var("GAME_WORKER_THREADS").ok().and_then(|threads| threads.parse().ok()).unwrap_or(0),
+            node_id: std::env::var("NODE_ID").or_else(|_| std::env::var("HOSTNAME")).unwrap_or_else(|_| "local".to_string()),
+            directory_dir: std::env::var("DIRECTORY_DIR").ok().map(PathBuf::from),
+        }
+    }
+
+    /// Where clients and other nodes reach this server. Only a single node listening on a
+    /// specific address can leave `PUBLIC_URL` out and be reached at its bind address, otherwise
+    /// clients would be sent somewhere like `0.0.0.0`.
+    pub fn public_url(&self) -> Result<String, String> {
+        if let Some(public_url) = &self.public_url {
+            return Ok(public_url.to_string());
+        }
+        if self.directory_dir.is_some() {
+            return Err("PUBLIC_URL has to be set when games are shared with other nodes through DIRECTORY_DIR".to_string());
+        }
+        if self.bind_address.parse::<SocketAddr>().is_ok_and(|address| address.ip().is_unspecified()) {
+            return Err(format!("PUBLIC_URL has to be set when listening on every interface ({})", self.bind_address));
+        }
+        Ok(format!("http://{}", self.bind_address))
+    }
+}
@@ -0,0 +1,77 @@
+use std::sync::Arc;
+use axum::extract::State;
+use metrics::{describe_counter, describe_gauge, describe_histogram, gauge, Unit};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use crate::games_server::all_games_state::AllGamesState;
+use crate::games_server::all_games_state::game_state::GamePhase;
+
+pub const GAMES: &str = "snake_games";
+pub const CONNECTED_SOCKETS: &str = "snake_connected_sockets";
+pub const MESSAGES_RECEIVED: &str = "snake_messages_received_total";
+pub const MESSAGES_SENT: &str = "snake_messages_sent_total";
+pub const TICK_DURATION: &str = "snake_tick_duration_seconds";
+pub const TICK_OVERRUNS: &str = "snake_tick_overruns_total";
+pub const TICKS_LATE: &str = "snake_ticks_late_total";
+pub const BROADCAST_LAGGED: &str = "snake_broadcast_lagged_total";
+pub const BROADCAST_SKIPPED_MESSAGES: &str = "snake_broadcast_skipped_messages_total";
+pub const POWER_UPS_PURCHASED: &str = "snake_power_ups_purchased_total";
+pub const POWER_UP_REVENUE: &str = "snake_power_up_revenue";
+pub const AUTH_FAILURES: &str = "snake_auth_failures_total";
+pub const RATE_LIMITED_MESSAGES: &str = "snake_rate_limited_messages_total";
+pub const CLIENTS_DISCONNECTED: &str = "snake_clients_disconnected_total";
+pub const INPUTS_DROPPED: &str = "snake_inputs_dropped_total";
+pub const PLAYER_RTT: &str = "snake_player_rtt_seconds";
+pub const LAG_COMPENSATED_TURNS: &str = "snake_lag_compensated_turns_total";
+
+/// Starts recording metrics, the handle renders them for Prometheus to scrape
+pub fn install_recorder() -> Result<PrometheusHandle, String> {
+    let handle = PrometheusBuilder::new()
+        // ticks should take well under the 100ms they get
+        .set_buckets_for_metric(Matcher::Full(TICK_DURATION.to_string()), &[0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25])
+        .map_err(|err| err.to_string())?
+        .set_buckets_for_metric(Matcher::Full(PLAYER_RTT.to_string()), &[0.01, 0.025, 0.05, 0.1, 0.15, 0.25, 0.5, 1.0, 2.5])
+        .map_err(|err| err.to_string())?
+        .install_recorder()
+        .map_err(|err| err.to_string())?;
+
+    describe_gauge!(GAMES, "Games on this server by phase");
+    describe_gauge!(CONNECTED_SOCKETS, "Open player websockets");
+    describe_counter!(MESSAGES_RECEIVED, "Messages received from players");
+    describe_counter!(MESSAGES_SENT, "Messages sent to players");
+    describe_histogram!(TICK_DURATION, Unit::Seconds, "How long each game tick took to run");
+    describe_counter!(TICK_OVERRUNS, "Game ticks that took longer than the time between ticks");
+    describe_counter!(TICKS_LATE, "Game ticks that started a whole tick or more after they were due");
+    describe_counter!(BROADCAST_LAGGED, "Times a player fell so far behind that messages to them were dropped");
+    describe_counter!(BROADCAST_SKIPPED_MESSAGES, "Messages dropped because a player fell behind");
+    describe_counter!(POWER_UPS_PURCHASED, "Power ups bought, by type");
+    describe_gauge!(POWER_UP_REVENUE, "Total spent on power ups, by type, only ever goes up");
+    describe_counter!(AUTH_FAILURES, "Rejected player logins and management api calls, by reason");
+    describe_counter!(RATE_LIMITED_MESSAGES, "Player messages dropped for going over the rate limit, by type");
+    describe_counter!(CLIENTS_DISCONNECTED, "Player connections closed by the server, by reason");
+    describe_counter!(INPUTS_DROPPED, "Player turns dropped for going over the per tick cap");
+    describe_histogram!(PLAYER_RTT, Unit::Seconds, "Round trip time of pings to players");
+    describe_counter!(LAG_COMPENSATED_TURNS, "Late turns replayed from where the player turned");
+
+    Ok(handle)
+}
+
+pub async fn metrics(
+    State(state): State<Arc<AllGamesState>>
+) -> String {
+    let (mut waiting, mut playing, mut over) = (0, 0, 0);
+    for shard in state.games.shards() {
+        for game in shard.read().await.values() {
+            match game.status.borrow().phase {
+                GamePhase::WaitingForPlayers => waiting += 1,
+                GamePhase::Playing => playing += 1,
+                GamePhase::GameOver => over += 1,
+            }
+        }
+    }
+    gauge!(GAMES, "phase" => "waiting_for_players").set(waiting as f64);
+    gauge!(GAMES, "phase" => "playing").set(playing as f64);
+    gauge!(GAMES, "phase" => "game_over").set(over as f64);
+
+    state.metrics.run_upkeep();
+    state.metrics.render()
+}